@@ -0,0 +1,162 @@
+
+CREATE TABLE auth.consumed_refresh_token
+(
+    jti uuid NOT NULL,
+    family uuid NOT NULL,
+    user_ uuid NOT NULL,
+    expires_on timestamp(6) with time zone NOT NULL,
+    consumed_on timestamp(6) with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT consumed_refresh_token_pkey PRIMARY KEY (jti)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.consumed_refresh_token
+    OWNER to helix;
+
+CREATE INDEX consumed_refresh_token_expires_idx
+    ON auth.consumed_refresh_token USING btree (expires_on);
+
+
+CREATE TABLE auth.revoked_token_family
+(
+    family uuid NOT NULL,
+    user_ uuid NOT NULL,
+    revoked_on timestamp(6) with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT revoked_token_family_pkey PRIMARY KEY (family)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.revoked_token_family
+    OWNER to helix;
+
+
+CREATE TABLE auth.revoked_user
+(
+    user_ uuid NOT NULL,
+    revoked_on timestamp(6) with time zone NOT NULL,
+    CONSTRAINT revoked_user_pkey PRIMARY KEY (user_)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.revoked_user
+    OWNER to helix;
+
+
+CREATE TABLE auth.api_key
+(
+    id uuid NOT NULL,
+    label character varying NOT NULL,
+    hash bytea NOT NULL,
+    user_name character varying NOT NULL,
+    user_ uuid NOT NULL,
+    person_ uuid NOT NULL,
+    scopes text[] NOT NULL DEFAULT '{}',
+    created_on timestamp(6) with time zone NOT NULL DEFAULT now(),
+    expires_on timestamp(6) with time zone,
+    revoked_on timestamp(6) with time zone,
+    CONSTRAINT api_key_pkey PRIMARY KEY (id)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.api_key
+    OWNER to helix;
+
+CREATE INDEX api_key_user_idx
+    ON auth.api_key USING btree (user_);
+
+
+CREATE TABLE auth.auth_event
+(
+    id bigserial NOT NULL,
+    event character varying NOT NULL,
+    user_ uuid,
+    payload jsonb NOT NULL,
+    occurred_on timestamp(6) with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT auth_event_pkey PRIMARY KEY (id)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.auth_event
+    OWNER to helix;
+
+CREATE INDEX auth_event_user_idx
+    ON auth.auth_event USING btree (user_, occurred_on);
+
+
+CREATE TABLE auth.rate_limit_bucket
+(
+    key_ character varying NOT NULL,
+    tokens double precision NOT NULL,
+    allowed boolean NOT NULL,
+    updated_on timestamp(6) with time zone NOT NULL,
+    CONSTRAINT rate_limit_bucket_pkey PRIMARY KEY (key_)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.rate_limit_bucket
+    OWNER to helix;
+
+CREATE INDEX rate_limit_bucket_updated_idx
+    ON auth.rate_limit_bucket USING btree (updated_on);
+
+
+CREATE TABLE auth.rate_limit_failure
+(
+    key_ character varying NOT NULL,
+    failures integer NOT NULL,
+    last_failure_on timestamp(6) with time zone NOT NULL,
+    locked_until timestamp(6) with time zone,
+    CONSTRAINT rate_limit_failure_pkey PRIMARY KEY (key_)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.rate_limit_failure
+    OWNER to helix;
+
+CREATE INDEX rate_limit_failure_failure_idx
+    ON auth.rate_limit_failure USING btree (last_failure_on);
+
+
+CREATE TABLE auth.session
+(
+    id uuid NOT NULL,
+    user_ uuid NOT NULL,
+    device character varying,
+    ip character varying,
+    user_agent character varying,
+    created_on timestamp(6) with time zone NOT NULL DEFAULT now(),
+    last_seen_on timestamp(6) with time zone NOT NULL DEFAULT now(),
+    revoked_on timestamp(6) with time zone,
+    CONSTRAINT session_pkey PRIMARY KEY (id)
+)
+WITH (
+    OIDS = FALSE
+)
+TABLESPACE pg_default;
+
+ALTER TABLE auth.session
+    OWNER to helix;
+
+CREATE INDEX session_user_idx
+    ON auth.session USING btree (user_);
@@ -0,0 +1,110 @@
+use crate::error::*;
+use crate::password::constant_time_eq;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::prelude::*;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+//Makes keys easy to spot by secret scanners and in logs.
+pub const API_KEY_PREFIX: &str = "hlx";
+
+/// Long lived credential of a machine client, as stored.
+///
+/// The key itself is `hlx_<id>_<secret>` and is only known when generated:
+/// the store keeps the SHA-256 of the secret.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: uuid::Uuid,
+    pub label: String,
+    pub hash: Vec<u8>,
+    pub user: String,
+    pub user_uuid: uuid::Uuid,
+    pub person_uuid: uuid::Uuid,
+    pub scopes: Vec<String>,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: Option<DateTime<Utc>>,
+    pub revoked_on: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    //Returns the key to hand over to the client, and the record to store.
+    pub fn generate(
+        label: &str,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+    ) -> (String, ApiKey) {
+        let id = uuid::Uuid::new_v4();
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let secret = URL_SAFE_NO_PAD.encode(secret);
+
+        let key = ApiKey {
+            id,
+            label: label.to_owned(),
+            hash: hash(&secret),
+            user: user.to_owned(),
+            user_uuid: *user_uuid,
+            person_uuid: *person_uuid,
+            scopes: Vec::new(),
+            created_on: Utc::now(),
+            expires_on: None,
+            revoked_on: None,
+        };
+        (
+            format!("{}_{}_{}", API_KEY_PREFIX, id.to_simple(), secret),
+            key,
+        )
+    }
+
+    pub fn with_scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|s| (*s).to_owned()).collect();
+        self
+    }
+
+    pub fn expires_on(mut self, expires_on: DateTime<Utc>) -> Self {
+        self.expires_on = Some(expires_on);
+        self
+    }
+
+    //Safe to display, e.g. in a key management page.
+    pub fn display_prefix(&self) -> String {
+        format!(
+            "{}_{}",
+            API_KEY_PREFIX,
+            &self.id.to_simple().to_string()[..8]
+        )
+    }
+
+    //Checks the secret part and the key status.
+    pub fn check(&self, secret: &str, now: &DateTime<Utc>) -> HelixAuthResult<()> {
+        if !constant_time_eq(&hash(secret), &self.hash) {
+            return Err(HelixAuthError::InvalidToken);
+        }
+        if self.revoked_on.is_some() {
+            return Err(HelixAuthError::RevokedToken);
+        }
+        if self.expires_on.is_some_and(|expires_on| expires_on <= *now) {
+            return Err(HelixAuthError::ExpiredToken);
+        }
+        Ok(())
+    }
+}
+
+//Splits a key into its id and secret.
+pub fn parse(key: &str) -> HelixAuthResult<(uuid::Uuid, &str)> {
+    let mut parts = key.trim().splitn(3, '_');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(API_KEY_PREFIX), Some(id), Some(secret)) if !secret.is_empty() => {
+            let id = uuid::Uuid::parse_str(id).map_err(|_| HelixAuthError::MalformedToken)?;
+            Ok((id, secret))
+        }
+        _ => Err(HelixAuthError::MalformedToken),
+    }
+}
+
+fn hash(secret: &str) -> Vec<u8> {
+    Sha256::digest(secret.as_bytes()).to_vec()
+}
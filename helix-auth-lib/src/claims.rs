@@ -1,18 +1,116 @@
+use crate::api_key::ApiKey;
+use crate::config::AuthConfig;
+use crate::error::*;
 use chrono::prelude::*;
 use chrono::Duration;
 use jsonwebtoken::Validation;
-use std::env;
-use uuid;
+use serde_json::Value;
+use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize)]
+const ACCESS_TOKEN_SUBJECT: &str = "access-token";
+pub(crate) const REFRESH_TOKEN_SUBJECT: &str = "refresh-token";
+const API_KEY_SUBJECT: &str = "api-key";
+//Access token of an external OpenID Connect provider, mapped into `Claims`.
+pub(crate) const OIDC_TOKEN_SUBJECT: &str = "oidc-token";
+//Password checked, second factor pending: only exchangeable for full tokens.
+const MFA_TOKEN_SUBJECT: &str = "mfa-token";
+//Service tokens have the service identity as subject: `service:<name>`.
+const SERVICE_SUBJECT_PREFIX: &str = "service:";
+//Names of the `Claims` fields, a custom claim would overwrite them.
+const RESERVED_CLAIMS: &[&str] = &[
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "iat",
+    "iat_ms",
+    "nbf",
+    "jti",
+    "fid",
+    "roles",
+    "scopes",
+    "act",
+    "user",
+    "user_uuid",
+    "person_uuid",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub iss: String,
     pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
     pub user: String,
+    //Nil, and left out of the payload, for service principals.
+    #[serde(default, skip_serializing_if = "uuid::Uuid::is_nil")]
     pub user_uuid: uuid::Uuid,
+    #[serde(default, skip_serializing_if = "uuid::Uuid::is_nil")]
     pub person_uuid: uuid::Uuid,
     pub exp: i64,
     pub iat: i64,
+    //`iat` in milliseconds, revocations are compared against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_ms: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<uuid::Uuid>,
+    //Token family: every pair obtained by refreshing one login shares it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fid: Option<uuid::Uuid>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    //Admin acting as `user`, on impersonation tokens only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    //Application specific claims, serialized at the top level of the payload.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+/// RFC 8693 actor: the admin an impersonation token was issued to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: uuid::Uuid,
+    pub user: String,
+}
+
+/// Roles, scopes and custom claims granted to a user when issuing tokens.
+///
+/// They are copied into both tokens of the pair and carried over on refresh.
+#[derive(Debug, Clone, Default)]
+pub struct Grants {
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+    pub extra: HashMap<String, Value>,
+}
+
+impl Grants {
+    pub fn new() -> Self {
+        Grants::default()
+    }
+
+    pub fn role(mut self, role: &str) -> Self {
+        self.roles.push(role.to_owned());
+        self
+    }
+
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_owned());
+        self
+    }
+
+    //Custom claim, refused when its name is one of the `Claims` fields.
+    pub fn extra(mut self, name: &str, value: Value) -> HelixAuthResult<Self> {
+        if RESERVED_CLAIMS.contains(&name) {
+            return Err(HelixAuthError::ReservedClaim(name.to_owned()));
+        }
+        self.extra.insert(name.to_owned(), value);
+        Ok(self)
+    }
 }
 
 impl Claims {
@@ -27,77 +125,345 @@ impl Claims {
     pub fn get_person_uuid(&self) -> &uuid::Uuid {
         &self.person_uuid
     }
+
+    pub fn get_jti(&self) -> Option<&uuid::Uuid> {
+        self.jti.as_ref()
+    }
+
+    pub fn get_family(&self) -> Option<&uuid::Uuid> {
+        self.fid.as_ref()
+    }
+
+    pub fn get_roles(&self) -> &[String] {
+        &self.roles
+    }
+
+    pub fn get_scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn get_extra(&self, name: &str) -> Option<&Value> {
+        self.extra.get(name)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    //Principal authenticated with an API key rather than a token.
+    pub fn is_api_key(&self) -> bool {
+        self.sub == API_KEY_SUBJECT
+    }
+
+    //Issued at or before `instant`, to the millisecond when `iat_ms` is set:
+    //tokens of the same second are told apart.
+    pub(crate) fn issued_until(&self, instant: &DateTime<Utc>) -> bool {
+        match self.iat_ms {
+            Some(iat_ms) => iat_ms <= instant.timestamp_millis(),
+            None => self.iat <= instant.timestamp(),
+        }
+    }
+
+    //Principal authenticated by an external OpenID Connect provider.
+    pub fn is_oidc(&self) -> bool {
+        self.sub == OIDC_TOKEN_SUBJECT
+    }
+
+    pub fn get_actor(&self) -> Option<&Actor> {
+        self.act.as_ref()
+    }
+
+    //Token of `user`, used by the admin of `act`.
+    pub fn is_impersonated(&self) -> bool {
+        self.act.is_some()
+    }
+
+    //Backend service calling with a service token, rather than a user.
+    pub fn is_service(&self) -> bool {
+        self.get_service().is_some()
+    }
+
+    pub fn get_service(&self) -> Option<&str> {
+        self.sub
+            .strip_prefix(SERVICE_SUBJECT_PREFIX)
+            .filter(|name| !name.is_empty())
+    }
+
+    //Token accepted on protected routes: user access token or service token.
+    pub(crate) fn is_access(&self) -> bool {
+        self.sub == ACCESS_TOKEN_SUBJECT || self.is_service()
+    }
+
+    pub(crate) fn is_refresh(&self) -> bool {
+        self.sub == REFRESH_TOKEN_SUBJECT
+    }
+
+    pub fn get_audience(&self) -> Option<&str> {
+        self.aud.as_deref()
+    }
+
+    pub fn get_grants(&self) -> Grants {
+        Grants {
+            roles: self.roles.clone(),
+            scopes: self.scopes.clone(),
+            extra: self.extra.clone(),
+        }
+    }
+
+    pub fn with_grants(mut self, grants: &Grants) -> Self {
+        self.roles = grants.roles.clone();
+        self.scopes = grants.scopes.clone();
+        //Reserved names inserted directly in `extra` are dropped.
+        self.extra = grants
+            .extra
+            .iter()
+            .filter(|(name, _)| !RESERVED_CLAIMS.contains(&name.as_str()))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        self
+    }
+
+    //Service the token is meant for, the configured audience when `None`.
+    pub fn with_audience(mut self, audience: Option<&str>) -> Self {
+        if let Some(audience) = audience {
+            self.aud = Some(audience.to_owned());
+        }
+        self
+    }
 }
 
 pub fn get_access_token_claims(
+    config: &AuthConfig,
     user: &str,
     user_uuid: &uuid::Uuid,
     person_uuid: &uuid::Uuid,
+    family: &uuid::Uuid,
 ) -> Claims {
-    let sub = "access-token".to_owned();
-    let iss = env::var("API_HOSTNAME").expect("API_HOSTNAME not found.");
-    let exp: i64 = env::var("HELIX_ACCESS_TOKEN_MAX_LIFETIME")
-        .expect("HELIX_ACCESS_TOKEN_MAX_LIFETIME not found.")
-        .parse()
-        .unwrap();
-
-    get_token_claims(&iss, &sub, user, user_uuid, person_uuid, exp)
+    get_token_claims(
+        config,
+        ACCESS_TOKEN_SUBJECT,
+        user,
+        user_uuid,
+        person_uuid,
+        family,
+        config.access_token_lifetime(),
+    )
 }
 
 pub fn get_refresh_token_claims(
+    config: &AuthConfig,
     user: &str,
     user_uuid: &uuid::Uuid,
     person_uuid: &uuid::Uuid,
+    family: &uuid::Uuid,
 ) -> Claims {
-    let sub = "refresh-token".to_owned();
-    let iss = env::var("API_HOSTNAME").expect("API_HOSTNAME not found.");
-    let exp: i64 = env::var("HELIX_REFRESH_TOKEN_MAX_LIFETIME")
-        .expect("HELIX_REFRESH_TOKEN_MAX_LIFETIME not found.")
-        .parse()
-        .unwrap();
+    get_token_claims(
+        config,
+        REFRESH_TOKEN_SUBJECT,
+        user,
+        user_uuid,
+        person_uuid,
+        family,
+        config.refresh_token_lifetime(),
+    )
+}
+
+//Claims-shaped principal of an API key, valid for the current request only.
+pub fn get_api_key_claims(config: &AuthConfig, key: &ApiKey) -> Claims {
+    let utc: DateTime<Utc> = Utc::now();
+    Claims {
+        iss: config.issuer().to_owned(),
+        sub: API_KEY_SUBJECT.to_owned(),
+        aud: config.audience().map(str::to_owned),
+        user: key.user.clone(),
+        user_uuid: key.user_uuid,
+        person_uuid: key.person_uuid,
+        exp: key
+            .expires_on
+            .unwrap_or_else(|| utc + config.access_token_lifetime())
+            .timestamp(),
+        iat: key.created_on.timestamp(),
+        iat_ms: None,
+        nbf: None,
+        jti: Some(key.id),
+        fid: None,
+        roles: Vec::new(),
+        scopes: key.scopes.clone(),
+        act: None,
+        extra: HashMap::new(),
+    }
+}
+
+//Short-lived token of a backend service, restricted to `scopes`.
+pub fn get_service_token_claims(config: &AuthConfig, service: &str, scopes: &[String]) -> Claims {
+    let utc: DateTime<Utc> = Utc::now();
+    Claims {
+        iss: config.issuer().to_owned(),
+        sub: format!("{}{}", SERVICE_SUBJECT_PREFIX, service),
+        aud: config.audience().map(str::to_owned),
+        user: service.to_owned(),
+        user_uuid: uuid::Uuid::nil(),
+        person_uuid: uuid::Uuid::nil(),
+        exp: (utc + config.service_token_lifetime()).timestamp(),
+        iat: utc.timestamp(),
+        iat_ms: Some(utc.timestamp_millis()),
+        nbf: match config.not_before() {
+            true => Some(utc.timestamp()),
+            false => None,
+        },
+        jti: Some(uuid::Uuid::new_v4()),
+        fid: None,
+        roles: Vec::new(),
+        scopes: scopes.to_vec(),
+        act: None,
+        extra: HashMap::new(),
+    }
+}
 
-    get_token_claims(&iss, &sub, user, user_uuid, person_uuid, exp)
+pub fn get_mfa_token_claims(
+    config: &AuthConfig,
+    user: &str,
+    user_uuid: &uuid::Uuid,
+    person_uuid: &uuid::Uuid,
+    family: &uuid::Uuid,
+) -> Claims {
+    get_token_claims(
+        config,
+        MFA_TOKEN_SUBJECT,
+        user,
+        user_uuid,
+        person_uuid,
+        family,
+        config.mfa_token_lifetime(),
+    )
 }
 
-pub fn get_access_token_validation() -> Validation {
-    let sub = "access-token".to_owned();
-    let iss = env::var("API_HOSTNAME").expect("API_HOSTNAME not found.");
+pub fn get_access_token_validation(config: &AuthConfig) -> Validation {
+    get_token_validation(config, ACCESS_TOKEN_SUBJECT)
+}
 
-    get_token_validation(&iss, &sub)
+//Access or service token, the subject is checked with `Claims::is_access`.
+pub(crate) fn get_principal_validation(config: &AuthConfig) -> Validation {
+    let mut validation = get_token_validation(config, ACCESS_TOKEN_SUBJECT);
+    validation.sub = None;
+    validation
 }
 
-#[allow(dead_code)]
-pub fn get_refresh_token_validation() -> Validation {
-    let sub = "refresh-token".to_owned();
-    let iss = env::var("API_HOSTNAME").expect("API_HOSTNAME not found.");
+//Refresh tokens come back to their issuer only: their `aud` is the target of
+//the access tokens, carried over on refresh, and not checked.
+pub fn get_refresh_token_validation(config: &AuthConfig) -> Validation {
+    let mut validation = get_token_validation(config, REFRESH_TOKEN_SUBJECT);
+    validation.aud = None;
+    validation.set_required_spec_claims(&["exp"]);
+    validation
+}
 
-    get_token_validation(&iss, &sub)
+pub fn get_mfa_token_validation(config: &AuthConfig) -> Validation {
+    get_token_validation(config, MFA_TOKEN_SUBJECT)
 }
 
 fn get_token_claims(
-    iss: &str,
+    config: &AuthConfig,
     sub: &str,
     user: &str,
     user_uuid: &uuid::Uuid,
     person_uuid: &uuid::Uuid,
-    exp: i64,
+    family: &uuid::Uuid,
+    lifetime: Duration,
 ) -> Claims {
     let utc: DateTime<Utc> = Utc::now();
     Claims {
-        iss: iss.to_owned(),
+        iss: config.issuer().to_owned(),
         sub: sub.to_owned(),
+        aud: config.audience().map(str::to_owned),
         user: user.to_owned(),
         user_uuid: *user_uuid,
         person_uuid: *person_uuid,
-        exp: (utc + Duration::minutes(exp)).timestamp(),
+        exp: (utc + lifetime).timestamp(),
         iat: utc.timestamp(),
+        iat_ms: Some(utc.timestamp_millis()),
+        nbf: match config.not_before() {
+            true => Some(utc.timestamp()),
+            false => None,
+        },
+        jti: Some(uuid::Uuid::new_v4()),
+        fid: Some(*family),
+        roles: Vec::new(),
+        scopes: Vec::new(),
+        act: None,
+        extra: HashMap::new(),
+    }
+}
+
+fn get_token_validation(config: &AuthConfig, sub: &str) -> Validation {
+    let mut validation = Validation::default();
+    validation.set_issuer(&[config.issuer()]);
+    validation.sub = Some(sub.to_owned());
+    validation.leeway = config.leeway();
+    //Checked when present, tokens issued without `nbf` stay valid.
+    validation.validate_nbf = true;
+    if let Some(audience) = config.audience() {
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "aud"]);
     }
+    validation
 }
 
-fn get_token_validation(iss: &str, sub: &str) -> Validation {
-    Validation {
-        iss: Some(iss.to_owned()),
-        sub: Some(sub.to_owned()),
-        ..Validation::default()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_claims_issued_before_grants() {
+        let legacy = r#"{"iss":"helix","sub":"access-token","user":"user",
+            "user_uuid":"00000000-0000-0000-0000-000000000000",
+            "person_uuid":"00000000-0000-0000-0000-000000000000","exp":1,"iat":0}"#;
+
+        let claims: Claims = serde_json::from_str(legacy).unwrap();
+        assert!(claims.get_roles().is_empty());
+        assert!(claims.get_scopes().is_empty());
+        assert!(claims.extra.is_empty());
+    }
+
+    #[test]
+    fn grants_are_serialized_at_top_level() {
+        let claims: Claims = serde_json::from_str(
+            r#"{"iss":"helix","sub":"access-token","user":"user",
+            "user_uuid":"00000000-0000-0000-0000-000000000000",
+            "person_uuid":"00000000-0000-0000-0000-000000000000","exp":1,"iat":0,
+            "roles":["admin"],"scopes":["read:tracker"],"tenant":"acme"}"#,
+        )
+        .unwrap();
+
+        assert!(claims.has_role("admin"));
+        assert!(claims.has_scope("read:tracker"));
+        assert_eq!(Some(&Value::from("acme")), claims.get_extra("tenant"));
+        assert_eq!(
+            Value::from("acme"),
+            serde_json::to_value(&claims).unwrap()["tenant"]
+        );
+    }
+
+    #[test]
+    fn reserved_names_are_not_custom_claims() {
+        assert!(matches!(
+            Grants::new().extra("sub", Value::from("admin")),
+            Err(HelixAuthError::ReservedClaim(name)) if name == "sub"
+        ));
+
+        let mut grants = Grants::new().extra("tenant", Value::from("acme")).unwrap();
+        grants.extra.insert("exp".to_owned(), Value::from(i64::MAX));
+        let claims: Claims = serde_json::from_value(serde_json::json!({
+            "iss": "helix", "sub": "access-token", "user": "user", "exp": 1, "iat": 0,
+            "user_uuid": uuid::Uuid::nil(), "person_uuid": uuid::Uuid::nil(),
+        }))
+        .unwrap();
+        let payload = serde_json::to_value(claims.with_grants(&grants)).unwrap();
+        assert_eq!(Value::from(1), payload["exp"]);
+        assert_eq!(Value::from("acme"), payload["tenant"]);
     }
 }
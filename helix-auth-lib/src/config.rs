@@ -0,0 +1,291 @@
+use crate::error::*;
+use crate::keyset::KeySet;
+use chrono::Duration;
+use std::env;
+use std::str::FromStr;
+
+/// Everything `HelixAuth` needs to issue and validate tokens.
+///
+/// Built once through `AuthConfig::builder` (or `AuthConfig::from_env`) and
+/// checked up front, so request handling never reads the environment.
+#[derive(Clone)]
+pub struct AuthConfig {
+    issuer: String,
+    keys: KeySet,
+    access_token_lifetime: Duration,
+    refresh_token_lifetime: Duration,
+    mfa_token_lifetime: Duration,
+    mfa_max_attempts: u32,
+    service_token_lifetime: Duration,
+    impersonation_token_lifetime: Duration,
+    leeway: u64,
+    audience: Option<String>,
+    not_before: bool,
+}
+
+impl AuthConfig {
+    pub fn builder(issuer: &str, keys: KeySet) -> AuthConfigBuilder {
+        AuthConfigBuilder {
+            config: AuthConfig {
+                issuer: issuer.to_owned(),
+                keys,
+                access_token_lifetime: Duration::minutes(15),
+                refresh_token_lifetime: Duration::days(1),
+                mfa_token_lifetime: Duration::minutes(5),
+                mfa_max_attempts: 5,
+                service_token_lifetime: Duration::minutes(5),
+                impersonation_token_lifetime: Duration::minutes(10),
+                leeway: 0,
+                audience: None,
+                not_before: false,
+            },
+        }
+    }
+
+    /// Reads the historical environment variables.
+    ///
+    /// `API_HOSTNAME` is the issuer, `HELIX_ACCESS_TOKEN_MAX_LIFETIME` and
+    /// `HELIX_REFRESH_TOKEN_MAX_LIFETIME` are lifetimes in minutes, or with an
+    /// `s`, `m`, `h` or `d` suffix (`90s`). Optional `HELIX_API_AUTH_LEEWAY`
+    /// (seconds), `HELIX_API_AUTH_AUDIENCE` and `HELIX_API_AUTH_NOT_BEFORE`.
+    /// Keys come from `KeySet::from_env`.
+    pub fn from_env() -> HelixAuthResult<AuthConfig> {
+        let mut builder = AuthConfig::builder(&required_var("API_HOSTNAME")?, KeySet::from_env()?)
+            .access_token_lifetime(lifetime_var("HELIX_ACCESS_TOKEN_MAX_LIFETIME")?)
+            .refresh_token_lifetime(lifetime_var("HELIX_REFRESH_TOKEN_MAX_LIFETIME")?);
+
+        if env::var("HELIX_API_AUTH_LEEWAY").is_ok() {
+            builder = builder.leeway(parsed_var("HELIX_API_AUTH_LEEWAY")?);
+        }
+        if let Ok(audience) = env::var("HELIX_API_AUTH_AUDIENCE") {
+            builder = builder.audience(&audience);
+        }
+        if env::var("HELIX_API_AUTH_NOT_BEFORE").is_ok() {
+            builder = builder.not_before(parsed_var("HELIX_API_AUTH_NOT_BEFORE")?);
+        }
+
+        builder.build()
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn keys(&self) -> &KeySet {
+        &self.keys
+    }
+
+    pub fn keys_mut(&mut self) -> &mut KeySet {
+        &mut self.keys
+    }
+
+    pub fn access_token_lifetime(&self) -> Duration {
+        self.access_token_lifetime
+    }
+
+    pub fn refresh_token_lifetime(&self) -> Duration {
+        self.refresh_token_lifetime
+    }
+
+    pub fn mfa_token_lifetime(&self) -> Duration {
+        self.mfa_token_lifetime
+    }
+
+    pub fn mfa_max_attempts(&self) -> u32 {
+        self.mfa_max_attempts
+    }
+
+    pub fn service_token_lifetime(&self) -> Duration {
+        self.service_token_lifetime
+    }
+
+    //Never longer than the access token lifetime.
+    pub fn impersonation_token_lifetime(&self) -> Duration {
+        self.impersonation_token_lifetime
+            .min(self.access_token_lifetime)
+    }
+
+    pub fn leeway(&self) -> u64 {
+        self.leeway
+    }
+
+    pub fn audience(&self) -> Option<&str> {
+        self.audience.as_deref()
+    }
+
+    pub fn not_before(&self) -> bool {
+        self.not_before
+    }
+}
+
+pub struct AuthConfigBuilder {
+    config: AuthConfig,
+}
+
+impl AuthConfigBuilder {
+    pub fn access_token_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.access_token_lifetime = lifetime;
+        self
+    }
+
+    pub fn refresh_token_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.refresh_token_lifetime = lifetime;
+        self
+    }
+
+    //Time left to enter the second factor after the password.
+    pub fn mfa_token_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.mfa_token_lifetime = lifetime;
+        self
+    }
+
+    //Wrong codes accepted before the mfa token is burnt.
+    pub fn mfa_max_attempts(mut self, attempts: u32) -> Self {
+        self.config.mfa_max_attempts = attempts;
+        self
+    }
+
+    //Service tokens are not refreshable, the service mints a new one instead.
+    pub fn service_token_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.service_token_lifetime = lifetime;
+        self
+    }
+
+    pub fn impersonation_token_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.impersonation_token_lifetime = lifetime;
+        self
+    }
+
+    //Clock skew tolerated on `exp` and `nbf`, in seconds.
+    pub fn leeway(mut self, seconds: u64) -> Self {
+        self.config.leeway = seconds;
+        self
+    }
+
+    //This service: the `aud` required in tokens, and the default `aud` of issued tokens.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.config.audience = Some(audience.to_owned());
+        self
+    }
+
+    //Adds `nbf` (the issue time) to issued tokens.
+    pub fn not_before(mut self, enabled: bool) -> Self {
+        self.config.not_before = enabled;
+        self
+    }
+
+    pub fn build(self) -> HelixAuthResult<AuthConfig> {
+        let config = self.config;
+
+        if config.issuer.trim().is_empty() {
+            return Err(invalid("issuer must not be empty"));
+        }
+        if config.access_token_lifetime <= Duration::zero() {
+            return Err(invalid("access token lifetime must be positive"));
+        }
+        if config.refresh_token_lifetime <= Duration::zero() {
+            return Err(invalid("refresh token lifetime must be positive"));
+        }
+        if config.mfa_token_lifetime <= Duration::zero() {
+            return Err(invalid("mfa token lifetime must be positive"));
+        }
+        if config.mfa_max_attempts == 0 {
+            return Err(invalid("mfa max attempts must be positive"));
+        }
+        if config.service_token_lifetime <= Duration::zero() {
+            return Err(invalid("service token lifetime must be positive"));
+        }
+        if config.impersonation_token_lifetime <= Duration::zero() {
+            return Err(invalid("impersonation token lifetime must be positive"));
+        }
+        if config.audience.as_deref().map(str::trim) == Some("") {
+            return Err(invalid("audience must not be empty"));
+        }
+        if config.keys.is_empty() {
+            return Err(invalid("at least one key is required"));
+        }
+
+        Ok(config)
+    }
+}
+
+fn invalid(message: &str) -> HelixAuthError {
+    HelixAuthError::InvalidConfiguration(message.to_owned())
+}
+
+fn required_var(name: &str) -> HelixAuthResult<String> {
+    env::var(name).map_err(|_| HelixAuthError::MissingConfiguration(name.to_owned()))
+}
+
+//Far above any sane token lifetime, far below what overflows `Duration`.
+const MAX_LIFETIME_SECONDS: i64 = 10 * 365 * 24 * 3600;
+
+//Minutes by default, as historically configured.
+fn lifetime_var(name: &str) -> HelixAuthResult<Duration> {
+    let value = required_var(name)?;
+    let value = value.trim();
+    let (amount, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "m"),
+    };
+
+    let malformed = || HelixAuthError::InvalidConfiguration(format!("{} is malformed", name));
+    let amount: i64 = amount.parse().map_err(|_| malformed())?;
+    let unit = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        _ => return Err(malformed()),
+    };
+    match amount.checked_mul(unit) {
+        Some(seconds) if seconds <= MAX_LIFETIME_SECONDS => Ok(Duration::seconds(seconds)),
+        _ => Err(HelixAuthError::InvalidConfiguration(format!(
+            "{} exceeds ten years",
+            name
+        ))),
+    }
+}
+
+fn parsed_var<T: FromStr>(name: &str) -> HelixAuthResult<T> {
+    required_var(name)?
+        .parse()
+        .map_err(|_| HelixAuthError::InvalidConfiguration(format!("{} is malformed", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::AuthKey;
+
+    #[test]
+    fn build_rejects_invalid_values() {
+        let keys = || KeySet::from(AuthKey::hmac(b"secret"));
+
+        assert!(AuthConfig::builder("helix", keys()).build().is_ok());
+        assert!(AuthConfig::builder(" ", keys()).build().is_err());
+        assert!(AuthConfig::builder("helix", KeySet::new()).build().is_err());
+        assert!(AuthConfig::builder("helix", keys())
+            .access_token_lifetime(Duration::zero())
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn lifetime_var_rejects_huge_values() {
+        let name = "HELIX_TEST_LIFETIME_VAR";
+
+        env::set_var(name, "90d");
+        assert_eq!(Duration::days(90), lifetime_var(name).unwrap());
+        env::set_var(name, "15");
+        assert_eq!(Duration::minutes(15), lifetime_var(name).unwrap());
+        for huge in &["9223372036854775807", "9223372036854775807d", "3651d"] {
+            env::set_var(name, huge);
+            assert!(matches!(
+                lifetime_var(name),
+                Err(HelixAuthError::InvalidConfiguration(_))
+            ));
+        }
+        env::remove_var(name);
+    }
+}
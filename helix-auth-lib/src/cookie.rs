@@ -0,0 +1,269 @@
+use crate::config::AuthConfig;
+use crate::error::*;
+use crate::password::constant_time_eq;
+use crate::validator::AuthRequest;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::Method;
+use actix_web::HttpMessage;
+
+/// Opt-in transport of the tokens in cookies, for browser frontends.
+///
+/// Access and refresh tokens are HttpOnly cookies. A third, readable cookie
+/// holds a CSRF token the frontend must echo in a header on unsafe methods
+/// (double submit).
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    access_cookie: String,
+    refresh_cookie: String,
+    csrf_cookie: String,
+    csrf_header: String,
+    domain: Option<String>,
+    path: String,
+    refresh_path: String,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            access_cookie: "helix_access".to_owned(),
+            refresh_cookie: "helix_refresh".to_owned(),
+            csrf_cookie: "helix_csrf".to_owned(),
+            csrf_header: "X-CSRF-Token".to_owned(),
+            domain: None,
+            path: "/".to_owned(),
+            refresh_path: "/".to_owned(),
+            secure: true,
+            same_site: SameSite::Strict,
+        }
+    }
+}
+
+impl CookieConfig {
+    pub fn new() -> Self {
+        CookieConfig::default()
+    }
+
+    pub fn access_cookie(mut self, name: &str) -> Self {
+        self.access_cookie = name.to_owned();
+        self
+    }
+
+    pub fn refresh_cookie(mut self, name: &str) -> Self {
+        self.refresh_cookie = name.to_owned();
+        self
+    }
+
+    pub fn csrf(mut self, cookie: &str, header: &str) -> Self {
+        self.csrf_cookie = cookie.to_owned();
+        self.csrf_header = header.to_owned();
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    //Restricts the refresh cookie to the refresh endpoint.
+    pub fn refresh_path(mut self, path: &str) -> Self {
+        self.refresh_path = path.to_owned();
+        self
+    }
+
+    //Only for local development over plain http.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Adds the cookies of a token pair, plus a fresh CSRF token, to a response.
+    pub fn set_tokens(
+        &self,
+        response: &mut HttpResponseBuilder,
+        config: &AuthConfig,
+        access: &str,
+        refresh: &str,
+    ) {
+        let access_lifetime = config.access_token_lifetime().num_seconds();
+        let refresh_lifetime = config.refresh_token_lifetime().num_seconds();
+        let csrf = uuid::Uuid::new_v4().to_simple().to_string();
+
+        response.cookie(self.cookie(
+            &self.access_cookie,
+            access,
+            &self.path,
+            access_lifetime,
+            true,
+        ));
+        response.cookie(self.cookie(
+            &self.refresh_cookie,
+            refresh,
+            &self.refresh_path,
+            refresh_lifetime,
+            true,
+        ));
+        response.cookie(self.cookie(
+            &self.csrf_cookie,
+            &csrf,
+            &self.path,
+            refresh_lifetime,
+            false,
+        ));
+    }
+
+    //Logout: expires every cookie set by `set_tokens`.
+    pub fn clear_tokens(&self, response: &mut HttpResponseBuilder) {
+        response.cookie(self.cookie(&self.access_cookie, "", &self.path, 0, true));
+        response.cookie(self.cookie(&self.refresh_cookie, "", &self.refresh_path, 0, true));
+        response.cookie(self.cookie(&self.csrf_cookie, "", &self.path, 0, false));
+    }
+
+    pub fn access_token<R: HttpMessage>(&self, req: &R) -> Option<String> {
+        req.cookie(&self.access_cookie)
+            .map(|c| c.value().to_owned())
+    }
+
+    pub fn refresh_token<R: HttpMessage>(&self, req: &R) -> Option<String> {
+        req.cookie(&self.refresh_cookie)
+            .map(|c| c.value().to_owned())
+    }
+
+    /// Double submit check: on unsafe methods the CSRF header must repeat the cookie.
+    pub fn check_csrf<R: HttpMessage>(&self, method: &Method, req: &R) -> HelixAuthResult<()> {
+        if is_safe(method) {
+            return Ok(());
+        }
+
+        let cookie = req.cookie(&self.csrf_cookie);
+        let header = req
+            .headers()
+            .get(self.csrf_header.as_str())
+            .and_then(|h| h.to_str().ok());
+        csrf_matches(cookie.as_ref().map(|c| c.value()), header)
+    }
+
+    pub(crate) fn request_access_token(&self, req: &AuthRequest) -> Option<String> {
+        req.get_cookie(&self.access_cookie)
+    }
+
+    pub(crate) fn check_request_csrf(&self, req: &AuthRequest) -> HelixAuthResult<()> {
+        if is_safe(&req.method) {
+            return Ok(());
+        }
+
+        csrf_matches(
+            req.get_cookie(&self.csrf_cookie).as_deref(),
+            req.get_header(&self.csrf_header),
+        )
+    }
+
+    fn cookie(
+        &self,
+        name: &str,
+        value: &str,
+        path: &str,
+        max_age: i64,
+        http_only: bool,
+    ) -> Cookie<'static> {
+        let mut cookie = Cookie::build(name.to_owned(), value.to_owned())
+            .path(path.to_owned())
+            .secure(self.secure)
+            .http_only(http_only)
+            .same_site(self.same_site)
+            .max_age(time::Duration::seconds(max_age))
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+}
+
+fn csrf_matches(cookie: Option<&str>, header: Option<&str>) -> HelixAuthResult<()> {
+    match (cookie, header) {
+        (Some(cookie), Some(header))
+            if !header.is_empty() && constant_time_eq(cookie.as_bytes(), header.as_bytes()) =>
+        {
+            Ok(())
+        }
+        _ => Err(HelixAuthError::InvalidCsrfToken),
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::AuthKey;
+    use crate::keyset::KeySet;
+    use crate::middleware::AuthValidator;
+    use crate::HelixAuth;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::sync::Arc;
+
+    #[actix_rt::test]
+    async fn cookie_token_requires_csrf_on_unsafe_methods() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let cookies = CookieConfig::new();
+        let user_uuid = uuid::Uuid::new_v4();
+        let auth = Arc::new(HelixAuth::new(config.clone()));
+        let (access, refresh) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        let mut login = HttpResponse::Ok();
+        cookies.set_tokens(&mut login, &config, &access, &refresh);
+        let login = login.finish();
+        let cookie = |name: &str| login.cookies().find(|c| c.name() == name).unwrap();
+        assert_eq!(Some(true), cookie("helix_access").http_only());
+        assert_eq!(Some(true), cookie("helix_refresh").http_only());
+        let (access, csrf) = (cookie("helix_access"), cookie("helix_csrf"));
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthValidator::builder(auth).cookies(cookies).build())
+                .route("/api/items", web::get().to(HttpResponse::Ok))
+                .route("/api/items", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = |req: test::TestRequest| {
+            req.uri("/api/items")
+                .cookie(access.clone())
+                .cookie(csrf.clone())
+        };
+
+        let response =
+            test::call_service(&mut app, request(test::TestRequest::get()).to_request()).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response =
+            test::call_service(&mut app, request(test::TestRequest::post()).to_request()).await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        let response = test::call_service(
+            &mut app,
+            request(test::TestRequest::post())
+                .header("X-CSRF-Token", csrf.value())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+    }
+}
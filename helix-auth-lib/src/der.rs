@@ -0,0 +1,190 @@
+//Minimal DER reader/writer, just enough to move keys between PEM/DER and JWK.
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_SEQUENCE: u8 = 0x30;
+
+pub const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+pub const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+pub const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+pub const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+//Returns (tag, content, rest).
+pub fn read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+
+    let (len, input) = if first < 0x80 {
+        (first as usize, input)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || input.len() < count {
+            return None;
+        }
+        let len = input[..count]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, &input[count..])
+    };
+
+    if input.len() < len {
+        return None;
+    }
+    Some((tag, &input[..len], &input[len..]))
+}
+
+pub fn read_expected(input: &[u8], expected: u8) -> Option<(&[u8], &[u8])> {
+    match read(input)? {
+        (tag, content, rest) if tag == expected => Some((content, rest)),
+        _ => None,
+    }
+}
+
+//Strips the sign byte DER puts in front of positive integers.
+pub fn unsigned(integer: &[u8]) -> &[u8] {
+    let mut integer = integer;
+    while integer.len() > 1 && integer[0] == 0 {
+        integer = &integer[1..];
+    }
+    integer
+}
+
+pub struct SubjectPublicKeyInfo<'a> {
+    pub algorithm: &'a [u8],
+    pub parameters: &'a [u8],
+    pub public_key: &'a [u8],
+}
+
+pub fn read_spki(input: &[u8]) -> Option<SubjectPublicKeyInfo<'_>> {
+    let (spki, _) = read_expected(input, TAG_SEQUENCE)?;
+    let (algorithm_identifier, rest) = read_expected(spki, TAG_SEQUENCE)?;
+    let (algorithm, parameters) = read_expected(algorithm_identifier, TAG_OID)?;
+    let parameters = match read_expected(parameters, TAG_OID) {
+        Some((oid, _)) => oid,
+        None => &[],
+    };
+    let (bits, _) = read_expected(rest, TAG_BIT_STRING)?;
+    let (_unused, public_key) = bits.split_first()?;
+
+    Some(SubjectPublicKeyInfo {
+        algorithm,
+        parameters,
+        public_key,
+    })
+}
+
+//Reads a PKCS#1 RSAPublicKey into (modulus, exponent).
+pub fn read_rsa_public_key(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (key, _) = read_expected(input, TAG_SEQUENCE)?;
+    let (n, rest) = read_expected(key, TAG_INTEGER)?;
+    let (e, _) = read_expected(rest, TAG_INTEGER)?;
+    Some((unsigned(n), unsigned(e)))
+}
+
+pub fn write(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .skip_while(|b| **b == 0)
+            .cloned()
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub fn write_unsigned(integer: &[u8]) -> Vec<u8> {
+    let integer = unsigned(integer);
+    let mut content = Vec::with_capacity(integer.len() + 1);
+    if !matches!(integer.first(), Some(b) if b & 0x80 == 0) {
+        content.push(0);
+    }
+    content.extend_from_slice(integer);
+    write(TAG_INTEGER, &content)
+}
+
+pub fn write_sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    write(TAG_SEQUENCE, &items.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //SubjectPublicKeyInfo of a P-256 key.
+    fn spki() -> Vec<u8> {
+        let mut point = vec![0, 0x04];
+        point.extend([7u8; 64].iter());
+        write_sequence(&[
+            write_sequence(&[write(TAG_OID, OID_EC_PUBLIC_KEY), write(TAG_OID, OID_P256)]),
+            write(TAG_BIT_STRING, &point),
+        ])
+    }
+
+    #[test]
+    fn lengths_and_integers_round_trip() {
+        for len in &[0usize, 0x7f, 0x80, 0xff, 0x100, 0x1_0000] {
+            let content = vec![1u8; *len];
+            let encoded = write(TAG_OCTET_STRING, &content);
+            assert_eq!(
+                Some((TAG_OCTET_STRING, &content[..], &[][..])),
+                read(&encoded)
+            );
+        }
+
+        let encoded = write_unsigned(&[0x80, 1]);
+        let (integer, _) = read_expected(&encoded, TAG_INTEGER).unwrap();
+        assert_eq!(&[0, 0x80, 1], integer);
+        assert_eq!(&[0x80, 1], unsigned(integer));
+        assert_eq!(&[0], unsigned(&[0, 0]));
+
+        let encoded = spki();
+        let info = read_spki(&encoded).unwrap();
+        assert_eq!(OID_EC_PUBLIC_KEY, info.algorithm);
+        assert_eq!(OID_P256, info.parameters);
+        assert_eq!(65, info.public_key.len());
+    }
+
+    #[test]
+    fn malformed_input_is_refused_without_panicking() {
+        assert_eq!(None, read(&[]));
+        assert_eq!(None, read(&[TAG_SEQUENCE]));
+        //Content shorter than its length.
+        assert_eq!(None, read(&[TAG_SEQUENCE, 0x03, 0x01]));
+        //Indefinite, oversized and truncated long form lengths.
+        assert_eq!(None, read(&[TAG_SEQUENCE, 0x80]));
+        assert_eq!(None, read(&[TAG_SEQUENCE, 0x85, 1, 0, 0, 0, 0]));
+        assert_eq!(None, read(&[TAG_SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff]));
+        assert_eq!(None, read(&[TAG_SEQUENCE, 0x82, 0x01]));
+        assert_eq!(None, read_expected(&[TAG_INTEGER, 0], TAG_SEQUENCE));
+        assert_eq!(
+            None,
+            read_rsa_public_key(&write_sequence(&[write_unsigned(&[1])]))
+        );
+        assert!(read_spki(&write_sequence(&[])).is_none());
+
+        //Every truncation and single byte corruption of a valid key.
+        let valid = spki();
+        for end in 0..valid.len() {
+            assert!(read_spki(&valid[..end]).is_none());
+        }
+        for index in 0..valid.len() {
+            for byte in &[0x00, 0x7f, 0x80, 0x84, 0xff] {
+                let mut corrupted = valid.clone();
+                corrupted[index] = *byte;
+                let _ = read_spki(&corrupted);
+                let _ = read_rsa_public_key(&corrupted);
+            }
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use crate::storage::error::StorageError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
 use std::result::Result;
 use thiserror::Error;
 
@@ -6,8 +9,141 @@ use thiserror::Error;
 pub enum HelixAuthError {
     #[error("Token invalid")]
     InvalidToken,
+    #[error("Token missing")]
+    MissingToken,
+    #[error("Token malformed")]
+    MalformedToken,
+    #[error("Token expired")]
+    ExpiredToken,
+    #[error("Token not valid yet")]
+    ImmatureToken,
+    #[error("Token issued by an unexpected issuer")]
+    InvalidIssuer,
+    #[error("Token issued for another audience")]
+    InvalidAudience,
+    #[error("Permission missing")]
+    InsufficientPermission,
+    #[error("Too many requests, retry in {0} seconds")]
+    TooManyRequests(u64),
+    #[error("CSRF token missing or invalid")]
+    InvalidCsrfToken,
+    #[error("One-time password invalid")]
+    InvalidOneTimePassword,
     #[error("Not found error")]
     NotFoundError,
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Missing configuration: {0}")]
+    MissingConfiguration(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfiguration(String),
+    #[error("Token revoked")]
+    RevokedToken,
+    #[error("Refresh token reused, token family revoked")]
+    ReusedToken,
+    #[error("Claim {0} is reserved")]
+    ReservedClaim(String),
+    #[error("Token generation failed: {0}")]
+    TokenGeneration(String),
+    #[error("OpenID Connect discovery failed: {0}")]
+    Discovery(String),
+    #[error("Password hashing failed: {0}")]
+    PasswordHash(String),
+    #[error("Storage error: {source}")]
+    Storage {
+        #[from]
+        source: StorageError,
+    },
+}
+
+impl HelixAuthError {
+    //Stable code sent in the `error` member of the JSON body.
+    pub fn code(&self) -> &'static str {
+        match self {
+            HelixAuthError::InvalidToken => "invalid_token",
+            HelixAuthError::MissingToken => "missing_token",
+            HelixAuthError::MalformedToken => "malformed_token",
+            HelixAuthError::ExpiredToken => "expired_token",
+            HelixAuthError::ImmatureToken => "immature_token",
+            HelixAuthError::InvalidIssuer => "invalid_issuer",
+            HelixAuthError::InvalidAudience => "invalid_audience",
+            HelixAuthError::InsufficientPermission => "insufficient_permission",
+            HelixAuthError::InvalidCsrfToken => "invalid_csrf_token",
+            HelixAuthError::TooManyRequests(_) => "too_many_requests",
+            HelixAuthError::InvalidOneTimePassword => "invalid_otp",
+            HelixAuthError::RevokedToken => "revoked_token",
+            HelixAuthError::ReusedToken => "reused_token",
+            HelixAuthError::NotFoundError => "not_found",
+            HelixAuthError::Storage { .. } => "unavailable",
+            _ => "server_error",
+        }
+    }
+
+    //Headers of the error response, shared by the actix and tower adapters.
+    pub(crate) fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(challenge) = self.challenge() {
+            headers.push(("WWW-Authenticate", challenge));
+        }
+        if let HelixAuthError::TooManyRequests(seconds) = self {
+            headers.push(("Retry-After", seconds.to_string()));
+        }
+        headers
+    }
+
+    pub(crate) fn body(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": self.code(),
+            "error_description": self.to_string(),
+        })
+    }
+
+    //RFC 6750 challenge, none for errors unrelated to the token.
+    fn challenge(&self) -> Option<String> {
+        let error = match self {
+            HelixAuthError::MissingToken => return Some("Bearer".to_owned()),
+            HelixAuthError::InsufficientPermission => "insufficient_scope",
+            HelixAuthError::InvalidToken
+            | HelixAuthError::MalformedToken
+            | HelixAuthError::ExpiredToken
+            | HelixAuthError::ImmatureToken
+            | HelixAuthError::InvalidIssuer
+            | HelixAuthError::InvalidAudience
+            | HelixAuthError::RevokedToken
+            | HelixAuthError::ReusedToken => "invalid_token",
+            _ => return None,
+        };
+
+        Some(format!(
+            "Bearer error=\"{}\", error_description=\"{}\"",
+            error,
+            self.to_string().replace('"', "'")
+        ))
+    }
+}
+
+impl ResponseError for HelixAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            HelixAuthError::InsufficientPermission | HelixAuthError::InvalidCsrfToken => {
+                StatusCode::FORBIDDEN
+            }
+            HelixAuthError::InvalidOneTimePassword => StatusCode::UNAUTHORIZED,
+            HelixAuthError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            HelixAuthError::NotFoundError => StatusCode::NOT_FOUND,
+            HelixAuthError::Storage { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            e if e.challenge().is_some() => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        for (name, value) in self.headers() {
+            response.header(name, value);
+        }
+        response.json(self.body())
+    }
 }
 
 //Define a generic error type to simplify return.
@@ -0,0 +1,129 @@
+use crate::error::HelixAuthError;
+
+/// Authentication events, see `HelixAuth::with_event_sink`.
+///
+/// An invalid credential rejected by `AuthValidator` yields both
+/// `ValidationFailed` and `RequestRejected`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuthEvent {
+    //`kind` is one of `token_pair`, `service`, `impersonation` or `mfa`.
+    TokenIssued {
+        kind: String,
+        user: String,
+        user_uuid: uuid::Uuid,
+    },
+    TokenRefreshed {
+        user: String,
+        user_uuid: uuid::Uuid,
+        family: Option<uuid::Uuid>,
+    },
+    //`reason` is the error code, as sent to the client.
+    ValidationFailed {
+        reason: String,
+        description: String,
+    },
+    RequestRejected {
+        method: String,
+        path: String,
+        peer: Option<String>,
+        reason: String,
+    },
+}
+
+impl AuthEvent {
+    pub(crate) fn issued(kind: &str, user: &str, user_uuid: &uuid::Uuid) -> Self {
+        AuthEvent::TokenIssued {
+            kind: kind.to_owned(),
+            user: user.to_owned(),
+            user_uuid: *user_uuid,
+        }
+    }
+
+    pub(crate) fn validation_failed(error: &HelixAuthError) -> Self {
+        AuthEvent::ValidationFailed {
+            reason: error.code().to_owned(),
+            description: error.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthEvent::TokenIssued { .. } => "token_issued",
+            AuthEvent::TokenRefreshed { .. } => "token_refreshed",
+            AuthEvent::ValidationFailed { .. } => "validation_failed",
+            AuthEvent::RequestRejected { .. } => "request_rejected",
+        }
+    }
+
+    pub fn user_uuid(&self) -> Option<&uuid::Uuid> {
+        match self {
+            AuthEvent::TokenIssued { user_uuid, .. }
+            | AuthEvent::TokenRefreshed { user_uuid, .. } => Some(user_uuid),
+            _ => None,
+        }
+    }
+}
+
+/// Receives authentication events.
+///
+/// Called on the request path: implementations must not block, and their
+/// failures must not fail authentication.
+pub trait AuthEventSink: Send + Sync {
+    fn record(&self, event: &AuthEvent);
+}
+
+/// Structured `tracing` events, under the `helix_auth` target.
+#[derive(Debug, Default)]
+pub struct TracingEventSink;
+
+impl AuthEventSink for TracingEventSink {
+    fn record(&self, event: &AuthEvent) {
+        match event {
+            AuthEvent::TokenIssued {
+                kind,
+                user,
+                user_uuid,
+            } => tracing::info!(
+                target: "helix_auth",
+                event = event.name(),
+                kind = kind.as_str(),
+                user = user.as_str(),
+                user_uuid = %user_uuid,
+            ),
+            AuthEvent::TokenRefreshed {
+                user,
+                user_uuid,
+                family,
+            } => tracing::info!(
+                target: "helix_auth",
+                event = event.name(),
+                user = user.as_str(),
+                user_uuid = %user_uuid,
+                family = ?family,
+            ),
+            AuthEvent::ValidationFailed {
+                reason,
+                description,
+            } => tracing::warn!(
+                target: "helix_auth",
+                event = event.name(),
+                reason = reason.as_str(),
+                description = description.as_str(),
+            ),
+            AuthEvent::RequestRejected {
+                method,
+                path,
+                peer,
+                reason,
+            } => tracing::warn!(
+                target: "helix_auth",
+                event = event.name(),
+                method = method.as_str(),
+                path = path.as_str(),
+                peer = ?peer,
+                reason = reason.as_str(),
+            ),
+        }
+    }
+}
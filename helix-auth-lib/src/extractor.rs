@@ -0,0 +1,226 @@
+use crate::claims::{Actor, Claims};
+use crate::error::HelixAuthError;
+use actix_web::dev::Payload;
+use actix_web::{Error, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use std::ops::Deref;
+
+/// Claims of the calling user, as decoded by `AuthValidator`.
+///
+/// Taking it as a handler argument answers 401 when the request went through
+/// without claims, e.g. on an exception uri, and 403 for service principals.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    claims: Claims,
+}
+
+impl AuthenticatedUser {
+    pub fn claims(&self) -> &Claims {
+        &self.claims
+    }
+
+    //Admin using the token of the user, see `HelixAuth::impersonate`.
+    pub fn impersonator(&self) -> Option<&Actor> {
+        self.claims.get_actor()
+    }
+
+    pub fn into_inner(self) -> Claims {
+        self.claims
+    }
+}
+
+impl Deref for AuthenticatedUser {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.claims
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match principal(req) {
+            Ok(Principal::User(claims)) => Ok(AuthenticatedUser { claims }),
+            Ok(Principal::Service(_)) => Err(HelixAuthError::InsufficientPermission.into()),
+            Err(e) => Err(e.into()),
+        })
+    }
+}
+
+/// Claims of the calling backend service, see `HelixAuth::issue_service_token`.
+///
+/// Answers 401 without claims and 403 for user principals.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedService {
+    claims: Claims,
+}
+
+impl AuthenticatedService {
+    pub fn name(&self) -> &str {
+        self.claims.get_service().unwrap_or_default()
+    }
+
+    pub fn claims(&self) -> &Claims {
+        &self.claims
+    }
+
+    pub fn into_inner(self) -> Claims {
+        self.claims
+    }
+}
+
+impl Deref for AuthenticatedService {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.claims
+    }
+}
+
+impl FromRequest for AuthenticatedService {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match principal(req) {
+            Ok(Principal::Service(claims)) => Ok(AuthenticatedService { claims }),
+            Ok(Principal::User(_)) => Err(HelixAuthError::InsufficientPermission.into()),
+            Err(e) => Err(e.into()),
+        })
+    }
+}
+
+/// Either kind of caller, for handlers serving users and services alike.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    User(Claims),
+    Service(Claims),
+}
+
+impl Principal {
+    pub fn claims(&self) -> &Claims {
+        match self {
+            Principal::User(claims) | Principal::Service(claims) => claims,
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(principal(req).map_err(Error::from))
+    }
+}
+
+fn principal(req: &HttpRequest) -> Result<Principal, HelixAuthError> {
+    match req.extensions().get::<Claims>() {
+        Some(claims) if claims.is_service() => Ok(Principal::Service(claims.clone())),
+        Some(claims) => Ok(Principal::User(claims.clone())),
+        None => Err(HelixAuthError::MissingToken),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AuthConfig;
+    use crate::keys::AuthKey;
+    use crate::keyset::KeySet;
+    use crate::middleware::AuthValidator;
+    use crate::HelixAuth;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::sync::Arc;
+
+    async fn me(user: AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.get_user().clone())
+    }
+
+    async fn sync(service: AuthenticatedService) -> HttpResponse {
+        HttpResponse::Ok().body(service.name().to_owned())
+    }
+
+    #[actix_rt::test]
+    async fn handlers_receive_decoded_claims() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (access, _) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthValidator::with_auth(
+                    auth,
+                    vec!["/api/public".to_owned()],
+                ))
+                .route("/api/me", web::get().to(me))
+                .route("/api/public", web::get().to(me)),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/api/me")
+            .header("Authorization", format!("Bearer {}", access))
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(&b"user"[..], &body[..]);
+
+        let request = test::TestRequest::get().uri("/api/public").to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+        //No space, non ASCII value
+        for value in &["Bearer", "Bearer \u{e9}t\u{e9}"] {
+            let request = test::TestRequest::get()
+                .uri("/api/me")
+                .header("Authorization", *value)
+                .to_request();
+            let response = test::call_service(&mut app, request).await;
+            assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn service_principals_are_told_apart_from_users() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let service = auth
+            .issue_service_token("billing", &["tracker:read"], None)
+            .unwrap();
+        let user_uuid = uuid::Uuid::new_v4();
+        let (user, _) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthValidator::with_auth(auth, vec![]))
+                .route("/api/me", web::get().to(me))
+                .route("/api/sync", web::get().to(sync)),
+        )
+        .await;
+
+        let cases = [
+            ("/api/sync", &service, StatusCode::OK),
+            ("/api/me", &service, StatusCode::FORBIDDEN),
+            ("/api/sync", &user, StatusCode::FORBIDDEN),
+        ];
+        for (uri, token, status) in cases.iter() {
+            let request = test::TestRequest::get()
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .to_request();
+            let response = test::call_service(&mut app, request).await;
+            assert_eq!(*status, response.status());
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::claims::Claims;
+use crate::error::HelixAuthError;
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpMessage, ResponseError};
+use futures::future::{ok, Either, Ready};
+
+/// Role or scope a request must hold, combinable with `AnyOf` and `AllOf`.
+#[derive(Debug, Clone)]
+pub enum Permission {
+    Role(String),
+    Scope(String),
+    AnyOf(Vec<Permission>),
+    AllOf(Vec<Permission>),
+}
+
+impl Permission {
+    pub fn is_granted_to(&self, claims: &Claims) -> bool {
+        match self {
+            Permission::Role(role) => claims.has_role(role),
+            Permission::Scope(scope) => claims.has_scope(scope),
+            Permission::AnyOf(permissions) => permissions.iter().any(|p| p.is_granted_to(claims)),
+            Permission::AllOf(permissions) => permissions.iter().all(|p| p.is_granted_to(claims)),
+        }
+    }
+}
+
+/// Route level authorization, to be wrapped inside an `AuthValidator`.
+///
+/// Reads the claims the validator stored in the request extensions: requests
+/// without claims get 401, requests lacking the permission get 403.
+pub struct RequirePermission {
+    permission: Rc<Permission>,
+}
+
+impl RequirePermission {
+    pub fn new(permission: Permission) -> Self {
+        RequirePermission {
+            permission: Rc::new(permission),
+        }
+    }
+}
+
+//Shorthands building a `RequirePermission` on scopes.
+pub struct RequireScope;
+
+#[allow(clippy::new_ret_no_self)]
+impl RequireScope {
+    pub fn new(scope: &str) -> RequirePermission {
+        RequirePermission::new(Permission::Scope(scope.to_owned()))
+    }
+
+    pub fn any_of(scopes: &[&str]) -> RequirePermission {
+        RequirePermission::new(Permission::AnyOf(scopes_of(scopes)))
+    }
+
+    pub fn all_of(scopes: &[&str]) -> RequirePermission {
+        RequirePermission::new(Permission::AllOf(scopes_of(scopes)))
+    }
+}
+
+//Shorthands building a `RequirePermission` on roles.
+pub struct RequireRole;
+
+#[allow(clippy::new_ret_no_self)]
+impl RequireRole {
+    pub fn new(role: &str) -> RequirePermission {
+        RequirePermission::new(Permission::Role(role.to_owned()))
+    }
+
+    pub fn any_of(roles: &[&str]) -> RequirePermission {
+        RequirePermission::new(Permission::AnyOf(roles_of(roles)))
+    }
+
+    pub fn all_of(roles: &[&str]) -> RequirePermission {
+        RequirePermission::new(Permission::AllOf(roles_of(roles)))
+    }
+}
+
+fn scopes_of(scopes: &[&str]) -> Vec<Permission> {
+    scopes
+        .iter()
+        .map(|s| Permission::Scope((*s).to_owned()))
+        .collect()
+}
+
+fn roles_of(roles: &[&str]) -> Vec<Permission> {
+    roles
+        .iter()
+        .map(|r| Permission::Role((*r).to_owned()))
+        .collect()
+}
+
+impl<S, B> Transform<S> for RequirePermission
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequirePermissionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequirePermissionMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            permission: self.permission.clone(),
+        })
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    permission: Rc<Permission>,
+}
+
+impl<S, B> Service for RequirePermissionMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let granted = req
+            .extensions()
+            .get::<Claims>()
+            .map(|claims| self.permission.is_granted_to(claims));
+
+        let granted = match granted {
+            Some(granted) => granted,
+            None => {
+                //Not authenticated
+                return Either::Right(ok(
+                    req.into_response(HelixAuthError::MissingToken.error_response().into_body())
+                ));
+            }
+        };
+
+        if !granted {
+            //Authenticated but not allowed
+            return Either::Right(ok(req.into_response(
+                HelixAuthError::InsufficientPermission
+                    .error_response()
+                    .into_body(),
+            )));
+        }
+
+        Either::Left(self.service.borrow_mut().call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claims::Grants;
+    use crate::config::AuthConfig;
+    use crate::keys::AuthKey;
+    use crate::keyset::KeySet;
+    use crate::middleware::AuthValidator;
+    use crate::HelixAuth;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::sync::Arc;
+
+    #[actix_rt::test]
+    async fn missing_permission_is_forbidden() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let user_uuid = uuid::Uuid::new_v4();
+        let grants = Grants::new().role("reader").scope("tracker:read");
+        let (access, _) = auth
+            .issue_tokens_with("user", &user_uuid, &user_uuid, &grants)
+            .unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .service(
+                    web::resource("/api/read")
+                        .wrap(RequireScope::any_of(&["tracker:read", "tracker:write"]))
+                        .route(web::get().to(HttpResponse::Ok)),
+                )
+                .service(
+                    web::resource("/api/write")
+                        .wrap(RequirePermission::new(Permission::AllOf(vec![
+                            Permission::Role("reader".to_owned()),
+                            Permission::Scope("tracker:write".to_owned()),
+                        ])))
+                        .route(web::get().to(HttpResponse::Ok)),
+                )
+                .wrap(AuthValidator::with_auth(auth, vec![])),
+        )
+        .await;
+        let request = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", access))
+                .to_request()
+        };
+
+        let response = test::call_service(&mut app, request("/api/read")).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response = test::call_service(&mut app, request("/api/write")).await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+    }
+}
@@ -0,0 +1,25 @@
+use crate::error::HelixAuthResult;
+use async_trait::async_trait;
+use chrono::prelude::*;
+
+/// Who impersonated whom, recorded before the token is handed out.
+#[derive(Debug, Clone)]
+pub struct ImpersonationEvent {
+    pub jti: uuid::Uuid,
+    pub actor: String,
+    pub actor_uuid: uuid::Uuid,
+    pub user: String,
+    pub user_uuid: uuid::Uuid,
+    pub reason: String,
+    pub issued_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+}
+
+/// Audit trail of impersonations, see `HelixAuth::with_impersonation_audit`.
+///
+/// A failing `record` aborts the impersonation: no token is issued without
+/// its audit entry.
+#[async_trait]
+pub trait ImpersonationAudit: Send + Sync {
+    async fn record(&self, event: &ImpersonationEvent) -> HelixAuthResult<()>;
+}
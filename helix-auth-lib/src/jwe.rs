@@ -0,0 +1,226 @@
+use crate::error::*;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use serde_json::Value;
+use std::env;
+use std::fs;
+
+const ENCRYPTION: &str = "A256GCM";
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Key encrypting the signed tokens, making their payload confidential.
+///
+/// Tokens are nested JWTs: the signed token is the plaintext of a compact
+/// JWE, content encrypted with A256GCM. `direct` keys are a shared 256 bit key
+/// (`dir`), `rsa_oaep_pem` keys encrypt a random content key for the public key
+/// (`RSA-OAEP`); only holders of the private key can read the tokens.
+#[derive(Clone)]
+pub struct EncryptionKey(Kind);
+
+#[derive(Clone)]
+enum Kind {
+    Direct(Vec<u8>),
+    RsaOaep(Box<RsaKeys>),
+}
+
+#[derive(Clone)]
+struct RsaKeys {
+    public_key: RsaPublicKey,
+    private_key: Option<RsaPrivateKey>,
+}
+
+impl EncryptionKey {
+    pub fn direct(secret: &[u8]) -> HelixAuthResult<Self> {
+        match secret.len() {
+            KEY_LEN => Ok(EncryptionKey(Kind::Direct(secret.to_vec()))),
+            len => Err(HelixAuthError::InvalidKey(format!(
+                "A256GCM expects a {} bytes key, got {}",
+                KEY_LEN, len
+            ))),
+        }
+    }
+
+    /// RSA key from PEM encoded keys, PKCS#8 or PKCS#1.
+    ///
+    /// Without a private key tokens are encrypted but never read back: the
+    /// issuing `HelixAuth` can't validate or refresh them. Pass `None` only
+    /// for services that issue tokens others validate, like service tokens.
+    pub fn rsa_oaep_pem(private_key: Option<&str>, public_key: &str) -> HelixAuthResult<Self> {
+        let invalid = |e: String| HelixAuthError::InvalidKey(e);
+        let public_key = RsaPublicKey::from_public_key_pem(public_key)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(public_key))
+            .map_err(|e| invalid(e.to_string()))?;
+        let private_key = private_key
+            .map(|pem| {
+                RsaPrivateKey::from_pkcs8_pem(pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+                    .map_err(|e| invalid(e.to_string()))
+            })
+            .transpose()?;
+
+        Ok(EncryptionKey(Kind::RsaOaep(Box::new(RsaKeys {
+            public_key,
+            private_key,
+        }))))
+    }
+
+    /// Loads the encryption key described by the environment, if any.
+    ///
+    /// `HELIX_API_AUTH_ENCRYPTION_KEY` holds a base64 encoded 256 bit key for
+    /// `dir`. Otherwise `HELIX_API_AUTH_ENCRYPTION_PUBLIC_KEY` and, to read
+    /// tokens, `HELIX_API_AUTH_ENCRYPTION_PRIVATE_KEY` are PEM files for RSA-OAEP.
+    pub fn from_env() -> HelixAuthResult<Option<Self>> {
+        if let Ok(secret) = env::var("HELIX_API_AUTH_ENCRYPTION_KEY") {
+            let secret = STANDARD.decode(secret.trim()).map_err(|e| {
+                HelixAuthError::InvalidKey(format!("HELIX_API_AUTH_ENCRYPTION_KEY: {}", e))
+            })?;
+            return EncryptionKey::direct(&secret).map(Some);
+        }
+
+        let public_key = match read_pem("HELIX_API_AUTH_ENCRYPTION_PUBLIC_KEY")? {
+            Some(pem) => pem,
+            None => return Ok(None),
+        };
+        let private_key = read_pem("HELIX_API_AUTH_ENCRYPTION_PRIVATE_KEY")?;
+        EncryptionKey::rsa_oaep_pem(private_key.as_deref(), &public_key).map(Some)
+    }
+
+    pub fn can_decrypt(&self) -> bool {
+        match &self.0 {
+            Kind::Direct(_) => true,
+            Kind::RsaOaep(keys) => keys.private_key.is_some(),
+        }
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match &self.0 {
+            Kind::Direct(_) => "dir",
+            Kind::RsaOaep(_) => "RSA-OAEP",
+        }
+    }
+
+    //Content key and its encrypted form, empty for `dir`.
+    fn new_content_key(&self) -> HelixAuthResult<(Vec<u8>, Vec<u8>)> {
+        match &self.0 {
+            Kind::Direct(key) => Ok((key.clone(), Vec::new())),
+            Kind::RsaOaep(keys) => {
+                let mut key = vec![0u8; KEY_LEN];
+                OsRng.fill_bytes(&mut key);
+                let encrypted = keys
+                    .public_key
+                    .encrypt(&mut OsRng, Oaep::new::<sha1::Sha1>(), &key)
+                    .map_err(|e| HelixAuthError::TokenGeneration(e.to_string()))?;
+                Ok((key, encrypted))
+            }
+        }
+    }
+
+    fn content_key(&self, encrypted_key: &[u8]) -> HelixAuthResult<Vec<u8>> {
+        match &self.0 {
+            Kind::Direct(key) if encrypted_key.is_empty() => Ok(key.clone()),
+            Kind::RsaOaep(keys) => match &keys.private_key {
+                Some(private_key) => private_key
+                    .decrypt(Oaep::new::<sha1::Sha1>(), encrypted_key)
+                    .map_err(|_| HelixAuthError::InvalidToken),
+                None => Err(HelixAuthError::InvalidToken),
+            },
+            _ => Err(HelixAuthError::InvalidToken),
+        }
+    }
+}
+
+//Compact JWE have five parts, signed tokens three.
+pub(crate) fn is_encrypted(token: &str) -> bool {
+    token.split('.').count() == 5
+}
+
+pub(crate) fn encrypt(key: &EncryptionKey, token: &str) -> HelixAuthResult<String> {
+    let header = serde_json::json!({
+        "alg": key.algorithm(),
+        "enc": ENCRYPTION,
+        "cty": "JWT",
+    });
+    let protected = URL_SAFE_NO_PAD.encode(header.to_string());
+    let (content_key, encrypted_key) = key.new_content_key()?;
+    let mut iv = [0u8; IV_LEN];
+    OsRng.fill_bytes(&mut iv);
+
+    let cipher = Aes256Gcm::new_from_slice(&content_key)
+        .map_err(|e| HelixAuthError::TokenGeneration(e.to_string()))?;
+    let mut ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: token.as_bytes(),
+                aad: protected.as_bytes(),
+            },
+        )
+        .map_err(|e| HelixAuthError::TokenGeneration(e.to_string()))?;
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_LEN);
+
+    Ok([
+        protected,
+        URL_SAFE_NO_PAD.encode(encrypted_key),
+        URL_SAFE_NO_PAD.encode(iv),
+        URL_SAFE_NO_PAD.encode(ciphertext),
+        URL_SAFE_NO_PAD.encode(tag),
+    ]
+    .join("."))
+}
+
+//The signed token inside, still to be validated.
+pub(crate) fn decrypt(key: &EncryptionKey, token: &str) -> HelixAuthResult<String> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let decoded = parts
+        .iter()
+        .map(|part| URL_SAFE_NO_PAD.decode(part))
+        .collect::<Result<Vec<Vec<u8>>, _>>()
+        .map_err(|_| HelixAuthError::MalformedToken)?;
+    let (header, encrypted_key, iv, ciphertext, tag) = match decoded.as_slice() {
+        [header, encrypted_key, iv, ciphertext, tag] => {
+            (header, encrypted_key, iv, ciphertext, tag)
+        }
+        _ => return Err(HelixAuthError::MalformedToken),
+    };
+
+    let header: Value =
+        serde_json::from_slice(header).map_err(|_| HelixAuthError::MalformedToken)?;
+    //Encrypted for another key type, or with an unsupported algorithm.
+    if header["alg"] != key.algorithm() || header["enc"] != ENCRYPTION || iv.len() != IV_LEN {
+        return Err(HelixAuthError::InvalidToken);
+    }
+
+    let content_key = key.content_key(encrypted_key)?;
+    let cipher =
+        Aes256Gcm::new_from_slice(&content_key).map_err(|_| HelixAuthError::InvalidToken)?;
+    let mut msg = ciphertext.clone();
+    msg.extend_from_slice(tag);
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(iv),
+            Payload {
+                msg: &msg,
+                aad: parts[0].as_bytes(),
+            },
+        )
+        .map_err(|_| HelixAuthError::InvalidToken)?;
+    String::from_utf8(plaintext).map_err(|_| HelixAuthError::MalformedToken)
+}
+
+fn read_pem(var: &str) -> HelixAuthResult<Option<String>> {
+    match env::var(var) {
+        Ok(path) => fs::read_to_string(&path)
+            .map(Some)
+            .map_err(|e| HelixAuthError::InvalidKey(format!("{}: {}", path, e))),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,462 @@
+use crate::der;
+use crate::error::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use rsa::pkcs1::EncodeRsaPrivateKey;
+use rsa::{BigUint, RsaPrivateKey};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+
+pub use jsonwebtoken::Algorithm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFamily {
+    Hmac,
+    Rsa,
+    Ec,
+    Ed,
+}
+
+impl KeyFamily {
+    pub fn of(algorithm: Algorithm) -> KeyFamily {
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => KeyFamily::Hmac,
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => KeyFamily::Rsa,
+            Algorithm::ES256 | Algorithm::ES384 => KeyFamily::Ec,
+            Algorithm::EdDSA => KeyFamily::Ed,
+        }
+    }
+}
+
+/// Key material used to sign and verify tokens.
+///
+/// HMAC keys share one secret for both operations. Asymmetric keys sign with
+/// the private key and verify with the public key; a key built without its
+/// private part can only verify.
+#[derive(Clone)]
+pub struct AuthKey {
+    algorithm: Algorithm,
+    kid: Option<String>,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
+    public_params: Option<AlgorithmParameters>,
+}
+
+impl AuthKey {
+    pub fn hmac(secret: &[u8]) -> AuthKey {
+        AuthKey {
+            algorithm: Algorithm::HS256,
+            kid: None,
+            encoding_key: Some(EncodingKey::from_secret(secret)),
+            decoding_key: DecodingKey::from_secret(secret),
+            public_params: None,
+        }
+    }
+
+    pub fn from_secret(algorithm: Algorithm, secret: &[u8]) -> HelixAuthResult<AuthKey> {
+        match KeyFamily::of(algorithm) {
+            KeyFamily::Hmac => Ok(AuthKey {
+                algorithm,
+                ..AuthKey::hmac(secret)
+            }),
+            _ => Err(HelixAuthError::InvalidKey(format!(
+                "{:?} is not an HMAC algorithm",
+                algorithm
+            ))),
+        }
+    }
+
+    /// Builds an asymmetric key from PEM encoded keys.
+    ///
+    /// The private key is PKCS#8 (or PKCS#1 for RSA) and the public key is
+    /// SubjectPublicKeyInfo. Pass `None` as private key for verify-only services.
+    pub fn from_pem(
+        algorithm: Algorithm,
+        private_key: Option<&[u8]>,
+        public_key: &[u8],
+    ) -> HelixAuthResult<AuthKey> {
+        let invalid = |e: jsonwebtoken::errors::Error| HelixAuthError::InvalidKey(e.to_string());
+
+        let (encoding_key, decoding_key) = match KeyFamily::of(algorithm) {
+            KeyFamily::Rsa => (
+                private_key
+                    .map(EncodingKey::from_rsa_pem)
+                    .transpose()
+                    .map_err(invalid)?,
+                DecodingKey::from_rsa_pem(public_key).map_err(invalid)?,
+            ),
+            KeyFamily::Ec => (
+                private_key
+                    .map(EncodingKey::from_ec_pem)
+                    .transpose()
+                    .map_err(invalid)?,
+                DecodingKey::from_ec_pem(public_key).map_err(invalid)?,
+            ),
+            KeyFamily::Ed => (
+                private_key
+                    .map(EncodingKey::from_ed_pem)
+                    .transpose()
+                    .map_err(invalid)?,
+                DecodingKey::from_ed_pem(public_key).map_err(invalid)?,
+            ),
+            KeyFamily::Hmac => {
+                return Err(HelixAuthError::InvalidKey(format!(
+                    "{:?} expects a shared secret, not a PEM key",
+                    algorithm
+                )))
+            }
+        };
+
+        let public_params = pem::parse(public_key)
+            .ok()
+            .and_then(|p| match p.tag.as_str() {
+                "RSA PUBLIC KEY" => rsa_params(&p.contents),
+                _ => spki_params(&p.contents),
+            });
+
+        Ok(AuthKey {
+            algorithm,
+            kid: None,
+            encoding_key,
+            decoding_key,
+            public_params,
+        })
+    }
+
+    /// Builds an asymmetric key from DER encoded keys.
+    ///
+    /// Private keys are PKCS#8 (PKCS#1 for RSA). Public keys are PKCS#1 for RSA
+    /// and the raw public point/bytes for EC and Ed25519.
+    pub fn from_der(
+        algorithm: Algorithm,
+        private_key: Option<&[u8]>,
+        public_key: &[u8],
+    ) -> HelixAuthResult<AuthKey> {
+        let (encoding_key, decoding_key, public_params) = match KeyFamily::of(algorithm) {
+            KeyFamily::Rsa => (
+                private_key.map(EncodingKey::from_rsa_der),
+                DecodingKey::from_rsa_der(public_key),
+                rsa_params(public_key),
+            ),
+            KeyFamily::Ec => (
+                private_key.map(EncodingKey::from_ec_der),
+                DecodingKey::from_ec_der(public_key),
+                ec_params(algorithm, public_key),
+            ),
+            KeyFamily::Ed => (
+                private_key.map(EncodingKey::from_ed_der),
+                DecodingKey::from_ed_der(public_key),
+                ed_params(public_key),
+            ),
+            KeyFamily::Hmac => {
+                return Err(HelixAuthError::InvalidKey(format!(
+                    "{:?} expects a shared secret, not a DER key",
+                    algorithm
+                )))
+            }
+        };
+
+        Ok(AuthKey {
+            algorithm,
+            kid: None,
+            encoding_key,
+            decoding_key,
+            public_params,
+        })
+    }
+
+    /// Builds a key from a single JWK JSON object.
+    ///
+    /// Public members are always required. Private members (`d`, `p`, `q`...
+    /// for RSA, `d` for EC/OKP, `k` for `oct`) make the key usable for signing.
+    pub fn from_jwk(value: &Value) -> HelixAuthResult<AuthKey> {
+        let invalid = |e: String| HelixAuthError::InvalidKey(e);
+        let member = |name: &str| -> HelixAuthResult<Option<Vec<u8>>> {
+            match value.get(name).and_then(Value::as_str) {
+                Some(v) => URL_SAFE_NO_PAD
+                    .decode(v)
+                    .map(Some)
+                    .map_err(|e| invalid(format!("'{}': {}", name, e))),
+                None => Ok(None),
+            }
+        };
+
+        let kid = value.get("kid").and_then(Value::as_str).map(str::to_owned);
+
+        if value.get("kty").and_then(Value::as_str) == Some("oct") {
+            let secret = member("k")?.ok_or_else(|| invalid("'k' is missing".to_owned()))?;
+            let algorithm = match value.get("alg").and_then(Value::as_str) {
+                Some(alg) => parse_algorithm(alg)?,
+                None => Algorithm::HS256,
+            };
+            return Ok(AuthKey::from_secret(algorithm, &secret)?.with_kid_opt(kid));
+        }
+
+        let jwk: Jwk = serde_json::from_value(value.clone()).map_err(|e| invalid(e.to_string()))?;
+        let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|e| invalid(e.to_string()))?;
+
+        let algorithm = match jwk.common.algorithm {
+            Some(alg) => alg,
+            None => match &jwk.algorithm {
+                AlgorithmParameters::RSA(_) => Algorithm::RS256,
+                AlgorithmParameters::EllipticCurve(p) => match p.curve {
+                    EllipticCurve::P256 => Algorithm::ES256,
+                    EllipticCurve::P384 => Algorithm::ES384,
+                    _ => return Err(invalid(format!("unsupported curve {:?}", p.curve))),
+                },
+                _ => Algorithm::EdDSA,
+            },
+        };
+
+        let encoding_key = match (&jwk.algorithm, member("d")?) {
+            (_, None) => None,
+            (AlgorithmParameters::RSA(_), Some(d)) => {
+                let integer = |name: &str| -> HelixAuthResult<BigUint> {
+                    member(name)?
+                        .map(|bytes| BigUint::from_bytes_be(&bytes))
+                        .ok_or_else(|| {
+                            invalid(format!("'{}' is required for RSA private keys", name))
+                        })
+                };
+                //The CRT members (`dp`, `dq`, `qi`) are derived from the primes.
+                let private_key = RsaPrivateKey::from_components(
+                    integer("n")?,
+                    integer("e")?,
+                    BigUint::from_bytes_be(&d),
+                    vec![integer("p")?, integer("q")?],
+                )
+                .and_then(|key| key.validate().map(|_| key))
+                .map_err(|e| invalid(e.to_string()))?;
+                let der = private_key
+                    .to_pkcs1_der()
+                    .map_err(|e| invalid(e.to_string()))?;
+                Some(EncodingKey::from_rsa_der(der.as_bytes()))
+            }
+            (AlgorithmParameters::EllipticCurve(p), Some(d)) => {
+                let (curve, size) = match p.curve {
+                    EllipticCurve::P256 => (der::OID_P256, 32),
+                    EllipticCurve::P384 => (der::OID_P384, 48),
+                    _ => return Err(invalid(format!("unsupported curve {:?}", p.curve))),
+                };
+                let coordinate = |name: &str| -> HelixAuthResult<Vec<u8>> {
+                    match member(name)? {
+                        Some(bytes) if bytes.len() == size => Ok(bytes),
+                        Some(_) => Err(invalid(format!("'{}' has the wrong length", name))),
+                        None => Err(invalid(format!(
+                            "'{}' is required for EC private keys",
+                            name
+                        ))),
+                    }
+                };
+                if d.len() != size {
+                    return Err(invalid("'d' has the wrong length".to_owned()));
+                }
+                let mut point = vec![0x04];
+                point.extend(coordinate("x")?);
+                point.extend(coordinate("y")?);
+                let mut bits = vec![0];
+                bits.extend(point);
+
+                let ec_private_key = der::write_sequence(&[
+                    der::write_unsigned(&[1]),
+                    der::write(der::TAG_OCTET_STRING, &d),
+                    der::write(0xa1, &der::write(der::TAG_BIT_STRING, &bits)),
+                ]);
+                Some(EncodingKey::from_ec_der(&der::write_sequence(&[
+                    der::write_unsigned(&[0]),
+                    der::write_sequence(&[
+                        der::write(der::TAG_OID, der::OID_EC_PUBLIC_KEY),
+                        der::write(der::TAG_OID, curve),
+                    ]),
+                    der::write(der::TAG_OCTET_STRING, &ec_private_key),
+                ])))
+            }
+            (AlgorithmParameters::OctetKeyPair(p), Some(d)) => {
+                if p.curve != EllipticCurve::Ed25519 {
+                    return Err(invalid(format!("unsupported curve {:?}", p.curve)));
+                }
+                if d.len() != 32 {
+                    return Err(invalid("'d' has the wrong length".to_owned()));
+                }
+                Some(EncodingKey::from_ed_der(&der::write_sequence(&[
+                    der::write_unsigned(&[0]),
+                    der::write_sequence(&[der::write(der::TAG_OID, der::OID_ED25519)]),
+                    der::write(
+                        der::TAG_OCTET_STRING,
+                        &der::write(der::TAG_OCTET_STRING, &d),
+                    ),
+                ])))
+            }
+            (_, Some(_)) => return Err(invalid("unsupported private key type".to_owned())),
+        };
+
+        Ok(AuthKey {
+            algorithm,
+            kid,
+            encoding_key,
+            decoding_key,
+            public_params: Some(jwk.algorithm),
+        })
+    }
+
+    /// Loads the key described by the environment.
+    ///
+    /// `HELIX_API_AUTH_ALGORITHM` defaults to HS256, which reads the secret
+    /// from `HELIX_API_AUTH_KEY`. Other algorithms read PEM or DER files from
+    /// `HELIX_API_AUTH_PUBLIC_KEY` and, when signing, `HELIX_API_AUTH_PRIVATE_KEY`.
+    /// `HELIX_API_AUTH_KEY_ID` optionally sets the `kid`.
+    pub fn from_env() -> HelixAuthResult<AuthKey> {
+        let algorithm = match env::var("HELIX_API_AUTH_ALGORITHM") {
+            Ok(value) => parse_algorithm(&value)?,
+            Err(_) => Algorithm::HS256,
+        };
+
+        let key = match KeyFamily::of(algorithm) {
+            KeyFamily::Hmac => {
+                let secret = env::var("HELIX_API_AUTH_KEY").map_err(|_| {
+                    HelixAuthError::MissingConfiguration("HELIX_API_AUTH_KEY".to_owned())
+                })?;
+                AuthKey::from_secret(algorithm, secret.as_bytes())
+            }
+            _ => {
+                let public_key = read_key_file("HELIX_API_AUTH_PUBLIC_KEY")?.ok_or_else(|| {
+                    HelixAuthError::MissingConfiguration("HELIX_API_AUTH_PUBLIC_KEY".to_owned())
+                })?;
+                let private_key = read_key_file("HELIX_API_AUTH_PRIVATE_KEY")?;
+
+                if is_pem(&public_key) {
+                    AuthKey::from_pem(algorithm, private_key.as_deref(), &public_key)
+                } else {
+                    AuthKey::from_der(algorithm, private_key.as_deref(), &public_key)
+                }
+            }
+        }?;
+
+        Ok(key.with_kid_opt(env::var("HELIX_API_AUTH_KEY_ID").ok()))
+    }
+
+    pub fn with_kid(self, kid: &str) -> AuthKey {
+        self.with_kid_opt(Some(kid.to_owned()))
+    }
+
+    fn with_kid_opt(mut self, kid: Option<String>) -> AuthKey {
+        self.kid = kid;
+        self
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    pub fn can_sign(&self) -> bool {
+        self.encoding_key.is_some()
+    }
+
+    /// Public JWK for this key, `None` for HMAC secrets which must never be published.
+    pub fn to_jwk(&self) -> Option<Jwk> {
+        let params = self.public_params.clone()?;
+        Some(Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                algorithm: Some(self.algorithm),
+                key_id: self.kid.clone(),
+                ..CommonParameters::default()
+            },
+            algorithm: params,
+        })
+    }
+
+    pub(crate) fn encoding_key(&self) -> Option<&EncodingKey> {
+        self.encoding_key.as_ref()
+    }
+
+    pub(crate) fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding_key
+    }
+}
+
+fn parse_algorithm(value: &str) -> HelixAuthResult<Algorithm> {
+    Algorithm::from_str(value)
+        .map_err(|_| HelixAuthError::InvalidKey(format!("unknown algorithm '{}'", value)))
+}
+
+fn read_key_file(var: &str) -> HelixAuthResult<Option<Vec<u8>>> {
+    match env::var(var) {
+        Ok(path) => fs::read(&path)
+            .map(Some)
+            .map_err(|e| HelixAuthError::InvalidKey(format!("{}: {}", path, e))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn is_pem(key: &[u8]) -> bool {
+    key.starts_with(b"-----BEGIN")
+}
+
+fn spki_params(spki: &[u8]) -> Option<AlgorithmParameters> {
+    let info = der::read_spki(spki)?;
+    match info.algorithm {
+        der::OID_RSA_ENCRYPTION => rsa_params(info.public_key),
+        der::OID_EC_PUBLIC_KEY if info.parameters == der::OID_P384 => {
+            ec_params(Algorithm::ES384, info.public_key)
+        }
+        der::OID_EC_PUBLIC_KEY => ec_params(Algorithm::ES256, info.public_key),
+        der::OID_ED25519 => ed_params(info.public_key),
+        _ => None,
+    }
+}
+
+fn rsa_params(pkcs1: &[u8]) -> Option<AlgorithmParameters> {
+    let (n, e) = der::read_rsa_public_key(pkcs1)?;
+    Some(AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: RSAKeyType::RSA,
+        n: URL_SAFE_NO_PAD.encode(n),
+        e: URL_SAFE_NO_PAD.encode(e),
+    }))
+}
+
+fn ec_params(algorithm: Algorithm, point: &[u8]) -> Option<AlgorithmParameters> {
+    let (curve, size) = match algorithm {
+        Algorithm::ES384 => (EllipticCurve::P384, 48),
+        _ => (EllipticCurve::P256, 32),
+    };
+    if point.len() != 1 + 2 * size || point[0] != 0x04 {
+        return None;
+    }
+    Some(AlgorithmParameters::EllipticCurve(
+        EllipticCurveKeyParameters {
+            key_type: EllipticCurveKeyType::EC,
+            curve,
+            x: URL_SAFE_NO_PAD.encode(&point[1..=size]),
+            y: URL_SAFE_NO_PAD.encode(&point[1 + size..]),
+        },
+    ))
+}
+
+fn ed_params(public_key: &[u8]) -> Option<AlgorithmParameters> {
+    if public_key.len() != 32 {
+        return None;
+    }
+    Some(AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+        key_type: OctetKeyPairType::OctetKeyPair,
+        curve: EllipticCurve::Ed25519,
+        x: URL_SAFE_NO_PAD.encode(public_key),
+    }))
+}
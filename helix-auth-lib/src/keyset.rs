@@ -0,0 +1,227 @@
+use crate::error::*;
+use crate::jwe::EncryptionKey;
+use crate::keys::AuthKey;
+use jsonwebtoken::jwk::JwkSet;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    //Signs new tokens and validates existing ones.
+    Current,
+    //Only validates: previous key being phased out or next key being rolled out.
+    Active,
+    //Kept for bookkeeping, tokens signed with it are rejected.
+    Retired,
+}
+
+/// The keys a `HelixAuth` instance signs and validates with.
+///
+/// Tokens are signed with the current key and carry its `kid`. Validation
+/// selects the key by `kid` and accepts any key that is not retired, so a
+/// key can be rotated without invalidating outstanding tokens.
+///
+/// With an encryption key, tokens are also encrypted (JWE). Signed and
+/// encrypted tokens are both accepted, for the time of the migration.
+#[derive(Clone, Default)]
+pub struct KeySet {
+    keys: Vec<(AuthKey, KeyStatus)>,
+    encryption: Option<EncryptionKey>,
+    encrypt: bool,
+}
+
+impl KeySet {
+    pub fn new() -> Self {
+        KeySet::default()
+    }
+
+    //Encrypts new tokens and reads encrypted ones.
+    pub fn with_encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self.encrypt = true;
+        self
+    }
+
+    //Reads encrypted tokens but still issues signed ones: first migration step,
+    //until every validating service holds the key.
+    pub fn with_decryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self.encrypt = false;
+        self
+    }
+
+    //Key new tokens are encrypted with.
+    pub fn encryption(&self) -> Option<&EncryptionKey> {
+        self.encryption.as_ref().filter(|_| self.encrypt)
+    }
+
+    //Key encrypted tokens are read with.
+    pub fn decryption(&self) -> Option<&EncryptionKey> {
+        self.encryption.as_ref().filter(|key| key.can_decrypt())
+    }
+
+    /// Adds a key. A key added as `Current` demotes the previous current key to `Active`.
+    pub fn add(mut self, key: AuthKey, status: KeyStatus) -> Self {
+        if status == KeyStatus::Current {
+            self.demote_current();
+        }
+        self.keys.push((key, status));
+        self
+    }
+
+    pub fn set_current(&mut self, kid: &str) -> HelixAuthResult<()> {
+        match self.position(kid) {
+            Some(index) if self.keys[index].0.can_sign() => {
+                self.demote_current();
+                self.keys[index].1 = KeyStatus::Current;
+                Ok(())
+            }
+            Some(_) => Err(HelixAuthError::InvalidKey(format!(
+                "key '{}' has no private part to sign with",
+                kid
+            ))),
+            None => Err(HelixAuthError::NotFoundError),
+        }
+    }
+
+    pub fn retire(&mut self, kid: &str) -> HelixAuthResult<()> {
+        match self.position(kid) {
+            Some(index) => {
+                self.keys[index].1 = KeyStatus::Retired;
+                Ok(())
+            }
+            None => Err(HelixAuthError::NotFoundError),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn status(&self, kid: &str) -> Option<KeyStatus> {
+        self.position(kid).map(|index| self.keys[index].1)
+    }
+
+    pub fn current(&self) -> Option<&AuthKey> {
+        self.keys
+            .iter()
+            .find(|(_, status)| *status == KeyStatus::Current)
+            .map(|(key, _)| key)
+    }
+
+    /// Key to validate a token with. Tokens without `kid` predate rotation
+    /// and are checked against the key without `kid`, or the current key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&AuthKey> {
+        let found = self
+            .keys
+            .iter()
+            .find(|(key, status)| key.kid() == kid && *status != KeyStatus::Retired)
+            .map(|(key, _)| key);
+
+        match kid {
+            Some(_) => found,
+            None => found.or_else(|| self.current()),
+        }
+    }
+
+    /// Loads a keyset from a JWKS document.
+    ///
+    /// Besides the standard members, each key may carry a `status`
+    /// (`current`, `active` or `retired`, defaults to `active`) and private
+    /// members for the keys this service signs with.
+    pub fn from_jwks(json: &str) -> HelixAuthResult<Self> {
+        let document: Value =
+            serde_json::from_str(json).map_err(|e| HelixAuthError::InvalidKey(e.to_string()))?;
+        let entries = document
+            .get("keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| HelixAuthError::InvalidKey("'keys' array is missing".to_owned()))?;
+
+        let mut keyset = KeySet::new();
+        for entry in entries {
+            let key = AuthKey::from_jwk(entry)?;
+            let status = match entry.get("status") {
+                Some(status) => serde_json::from_value(status.clone())
+                    .map_err(|e| HelixAuthError::InvalidKey(e.to_string()))?,
+                None => KeyStatus::Active,
+            };
+            if key.kid().is_none() {
+                return Err(HelixAuthError::InvalidKey(
+                    "every key of a JWKS needs a 'kid'".to_owned(),
+                ));
+            }
+            if status == KeyStatus::Current && !key.can_sign() {
+                return Err(HelixAuthError::InvalidKey(format!(
+                    "current key '{}' has no private part",
+                    key.kid().unwrap_or_default()
+                )));
+            }
+            keyset = keyset.add(key, status);
+        }
+        Ok(keyset)
+    }
+
+    pub fn from_jwks_file<P: AsRef<Path>>(path: P) -> HelixAuthResult<Self> {
+        let json = fs::read_to_string(path.as_ref()).map_err(|e| {
+            HelixAuthError::InvalidKey(format!("{}: {}", path.as_ref().display(), e))
+        })?;
+        KeySet::from_jwks(&json)
+    }
+
+    /// Loads `HELIX_API_AUTH_JWKS` when set, otherwise the single key from `AuthKey::from_env`.
+    ///
+    /// The encryption key is read by `EncryptionKey::from_env`. Set
+    /// `HELIX_API_AUTH_ENCRYPT_TOKENS` to `false` to only read encrypted tokens.
+    pub fn from_env() -> HelixAuthResult<Self> {
+        let keys = match env::var("HELIX_API_AUTH_JWKS") {
+            Ok(path) => KeySet::from_jwks_file(path)?,
+            Err(_) => KeySet::from(AuthKey::from_env()?),
+        };
+        match EncryptionKey::from_env()? {
+            Some(key) if env::var("HELIX_API_AUTH_ENCRYPT_TOKENS").as_deref() == Ok("false") => {
+                Ok(keys.with_decryption(key))
+            }
+            Some(key) => Ok(keys.with_encryption(key)),
+            None => Ok(keys),
+        }
+    }
+
+    /// Public keys other services need to validate our tokens.
+    ///
+    /// Retired keys and HMAC secrets are never exported.
+    pub fn to_jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter(|(_, status)| *status != KeyStatus::Retired)
+                .filter_map(|(key, _)| key.to_jwk())
+                .collect(),
+        }
+    }
+
+    fn position(&self, kid: &str) -> Option<usize> {
+        self.keys.iter().position(|(key, _)| key.kid() == Some(kid))
+    }
+
+    fn demote_current(&mut self) {
+        for (_, status) in self.keys.iter_mut() {
+            if *status == KeyStatus::Current {
+                *status = KeyStatus::Active;
+            }
+        }
+    }
+}
+
+impl From<AuthKey> for KeySet {
+    fn from(key: AuthKey) -> Self {
+        let status = match key.can_sign() {
+            true => KeyStatus::Current,
+            false => KeyStatus::Active,
+        };
+        KeySet::new().add(key, status)
+    }
+}
@@ -0,0 +1,238 @@
+use crate::error::*;
+use crate::validator::{AuthRequest, RequestValidator};
+use actix_web::http::Method;
+use actix_web::ResponseError;
+use futures::future::BoxFuture;
+use http::{header, Extensions, Request, Response};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+//Peer address of a request, from its extensions.
+type PeerFn = dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync;
+
+/// Tower adapter of the `RequestValidator`, for axum, hyper or tonic services.
+///
+/// Valid claims are inserted in the request extensions, like the actix
+/// middleware does. The peer address, used by rate limits, is read from a
+/// `SocketAddr` extension by default. Servers storing it elsewhere set `peer`,
+/// e.g. with axum:
+///
+/// ```ignore
+/// AuthLayer::new(validator)
+///     .peer(|ext| ext.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip()))
+/// ```
+#[derive(Clone)]
+pub struct AuthLayer {
+    validator: Arc<RequestValidator>,
+    peer: Arc<PeerFn>,
+}
+
+impl AuthLayer {
+    pub fn new(validator: RequestValidator) -> Self {
+        AuthLayer {
+            validator: Arc::new(validator),
+            peer: Arc::new(|ext: &Extensions| ext.get::<SocketAddr>().map(|addr| addr.ip())),
+        }
+    }
+
+    pub fn peer<F>(mut self, peer: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.peer = Arc::new(peer);
+        self
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            validator: self.validator.clone(),
+            peer: self.peer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    validator: Arc<RequestValidator>,
+    peer: Arc<PeerFn>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AuthService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: From<String>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        //The ready service goes into the future, its clone stays for the next call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let validator = self.validator.clone();
+        let request = auth_request(&req, (self.peer)(req.extensions()));
+
+        Box::pin(async move {
+            match validator.validate(&request).await {
+                Ok(Some(claims)) => {
+                    req.extensions_mut().insert(claims);
+                }
+                Ok(None) => {}
+                Err(e) => return Ok(error_response(&e)),
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+fn auth_request<B>(req: &Request<B>, peer: Option<IpAddr>) -> AuthRequest {
+    //Both crates parse the same method tokens.
+    let method = Method::from_bytes(req.method().as_str().as_bytes()).unwrap_or_default();
+    let mut request = AuthRequest::new(method, req.uri().path()).peer(peer);
+    for (name, value) in req.headers() {
+        request = request.header(name.as_str(), &String::from_utf8_lossy(value.as_bytes()));
+    }
+    request
+}
+
+fn error_response<B: From<String>>(error: &HelixAuthError) -> Response<B> {
+    let mut response = Response::builder()
+        .status(error.status_code().as_u16())
+        .header(header::CONTENT_TYPE, "application/json");
+    for (name, value) in error.headers() {
+        response = response.header(name, value);
+    }
+    response
+        .body(B::from(error.body().to_string()))
+        .expect("error responses have valid headers")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claims::Claims;
+    use crate::config::AuthConfig;
+    use crate::keys::AuthKey;
+    use crate::keyset::KeySet;
+    use crate::rate_limit::{Backoff, RateLimiter};
+    use crate::storage::mem_rate_limit_imp::MemRateLimitStore;
+    use crate::HelixAuth;
+    use futures::future::{ready, Ready};
+    use std::convert::Infallible;
+
+    //Answers the user of the claims injected by the layer.
+    #[derive(Clone)]
+    struct WhoAmI;
+
+    impl Service<Request<String>> for WhoAmI {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<String>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<String>) -> Self::Future {
+            let user = req
+                .extensions()
+                .get::<Claims>()
+                .map_or_else(|| "anonymous".to_owned(), |c| c.get_user().to_owned());
+            ready(Ok(Response::new(user)))
+        }
+    }
+
+    #[test]
+    fn layer_injects_claims_and_rejects_like_the_middleware() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (access, _) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+        let layer = AuthLayer::new(
+            RequestValidator::builder(auth)
+                .public("/api/status")
+                .build(),
+        );
+        let mut service = layer.layer(WhoAmI);
+        let mut call = |uri: &str, token: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            futures::executor::block_on(service.call(request.body(String::new()).unwrap())).unwrap()
+        };
+
+        let response = call("/api/status", None);
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("anonymous", response.body());
+
+        let response = call("/api/me", Some(&access));
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("user", response.body());
+
+        let response = call("/api/me", None);
+        assert_eq!(401, response.status().as_u16());
+        assert_eq!("Bearer", response.headers()["WWW-Authenticate"]);
+
+        let response = call("/api/me", Some("forged"));
+        assert_eq!(401, response.status().as_u16());
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        assert_eq!("malformed_token", body["error"]);
+    }
+
+    //Peer address stored like axum's `ConnectInfo`.
+    #[derive(Clone)]
+    struct ConnectInfo(SocketAddr);
+
+    #[test]
+    fn layer_locks_out_the_peer_read_by_the_peer_fn() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let limiter = RateLimiter::new(Arc::new(MemRateLimitStore::new())).backoff(Backoff::new(
+            1,
+            chrono::Duration::minutes(1),
+            chrono::Duration::minutes(1),
+        ));
+        let layer = AuthLayer::new(
+            RequestValidator::builder(Arc::new(HelixAuth::new(config)))
+                .rate_limiter(limiter)
+                .build(),
+        )
+        .peer(|ext| ext.get::<ConnectInfo>().map(|info| info.0.ip()));
+        let mut service = layer.layer(WhoAmI);
+        let mut call = |peer: &str| {
+            let mut request = Request::builder()
+                .uri("/api/me")
+                .header("Authorization", "Bearer forged")
+                .body(String::new())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(peer.parse().unwrap()));
+            futures::executor::block_on(service.call(request)).unwrap()
+        };
+
+        assert_eq!(401, call("192.0.2.1:4000").status().as_u16());
+        assert_eq!(429, call("192.0.2.1:4001").status().as_u16());
+        assert_eq!(401, call("192.0.2.2:4000").status().as_u16());
+    }
+}
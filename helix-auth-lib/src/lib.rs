@@ -1,58 +1,742 @@
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate serde_derive;
-mod claims;
+pub mod api_key;
+pub mod claims;
+pub mod config;
+pub mod cookie;
+mod der;
 pub mod error;
+pub mod event;
+pub mod extractor;
+pub mod guard;
+pub mod impersonation;
+pub mod jwe;
+pub mod keys;
+pub mod keyset;
+#[cfg(feature = "tower")]
+pub mod layer;
 pub mod middleware;
+pub mod oauth;
+pub mod oidc;
+pub mod password;
+pub mod rate_limit;
+pub mod routes;
+pub mod session;
+pub mod storage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod tokenizer;
+pub mod totp;
+pub mod validator;
 
-use crate::claims::Claims;
+use crate::api_key::ApiKey;
+use crate::claims::{Actor, Claims, Grants};
+use crate::config::AuthConfig;
 use crate::error::*;
+use crate::event::{AuthEvent, AuthEventSink};
+use crate::impersonation::{ImpersonationAudit, ImpersonationEvent};
+use crate::keyset::KeySet;
+use crate::oidc::OidcProvider;
+use crate::session::{DeviceInfo, Session};
+use crate::storage::traits::{ApiKeyStore, RevocationStore, SessionStore};
+use crate::totp::{RecoveryCodes, Totp};
 use actix_web::HttpRequest;
-use std::env;
-use uuid;
+use chrono::prelude::*;
+use jsonwebtoken::jwk::JwkSet;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub struct HelixAuth {
+    config: AuthConfig,
+    revocation_store: Option<Arc<dyn RevocationStore>>,
+    api_key_store: Option<Arc<dyn ApiKeyStore>>,
+    oidc_provider: Option<Arc<OidcProvider>>,
+    impersonation_audit: Option<Arc<dyn ImpersonationAudit>>,
+    event_sink: Option<Arc<dyn AuthEventSink>>,
+    session_store: Option<Arc<dyn SessionStore>>,
+    //Wrong codes per mfa token `jti`, with the token expiry.
+    mfa_failures: Mutex<HashMap<uuid::Uuid, (u32, i64)>>,
+}
 
-pub struct HelixAuth {}
 impl HelixAuth {
-    pub fn is_auth_token_valid(token: &str) -> HelixAuthResult<()> {
-        match HelixAuth::get_token_data(token).is_ok() {
+    pub fn new(config: AuthConfig) -> Self {
+        HelixAuth {
+            config,
+            revocation_store: None,
+            api_key_store: None,
+            oidc_provider: None,
+            impersonation_audit: None,
+            event_sink: None,
+            session_store: None,
+            mfa_failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //Enables refresh token rotation, reuse detection and `revoke`.
+    pub fn with_revocation_store(mut self, store: Arc<dyn RevocationStore>) -> Self {
+        self.revocation_store = Some(store);
+        self
+    }
+
+    //Enables `Authorization: ApiKey <key>` and the api key management methods.
+    pub fn with_api_key_store(mut self, store: Arc<dyn ApiKeyStore>) -> Self {
+        self.api_key_store = Some(store);
+        self
+    }
+
+    //Also accepts access tokens of an external OpenID Connect provider.
+    pub fn with_oidc_provider(mut self, provider: Arc<OidcProvider>) -> Self {
+        self.oidc_provider = Some(provider);
+        self
+    }
+
+    //Enables `impersonate`, every impersonation is recorded there.
+    pub fn with_impersonation_audit(mut self, audit: Arc<dyn ImpersonationAudit>) -> Self {
+        self.impersonation_audit = Some(audit);
+        self
+    }
+
+    //Receives issued and refreshed tokens, failed validations and, through
+    //`AuthValidator`, rejected requests.
+    pub fn with_event_sink(mut self, sink: Arc<dyn AuthEventSink>) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+
+    //Enables `start_session` and the session management methods.
+    pub fn with_session_store(mut self, store: Arc<dyn SessionStore>) -> Self {
+        self.session_store = Some(store);
+        self
+    }
+
+    pub fn from_env() -> HelixAuthResult<Self> {
+        Ok(HelixAuth::new(AuthConfig::from_env()?))
+    }
+
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
+    }
+
+    pub fn keys(&self) -> &KeySet {
+        self.config.keys()
+    }
+
+    pub fn keys_mut(&mut self) -> &mut KeySet {
+        self.config.keys_mut()
+    }
+
+    //Public keys to serve on a JWKS endpoint.
+    pub fn jwks(&self) -> JwkSet {
+        self.keys().to_jwks()
+    }
+
+    pub fn validate(&self, token: &str) -> HelixAuthResult<()> {
+        self.token_data(token).map(|_| ())
+    }
+
+    pub fn claimer(&self, req: &HttpRequest) -> Option<Claims> {
+        //Already decoded by the middleware.
+        if let Some(claims) = req.extensions().get::<Claims>() {
+            return Some(claims.clone());
+        }
+
+        let value = req.headers().get("Authorization")?.to_str().ok()?;
+        self.token_data(value).ok()
+    }
+
+    pub fn issue_tokens(
+        &self,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+    ) -> Result<(String, String), String> {
+        self.issue_tokens_with(user, user_uuid, person_uuid, &Grants::default())
+    }
+
+    //Same as `issue_tokens`, with roles, scopes and custom claims.
+    pub fn issue_tokens_with(
+        &self,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+        grants: &Grants,
+    ) -> Result<(String, String), String> {
+        let tokens = self.issue_token_pair(
+            user,
+            user_uuid,
+            person_uuid,
+            grants,
+            &uuid::Uuid::new_v4(),
+            None,
+        )?;
+        self.emit(AuthEvent::issued("token_pair", user, user_uuid));
+        Ok(tokens)
+    }
+
+    //Same as `issue_tokens_with`, for another helix service: `audience` must be
+    //the audience configured there. Refreshed pairs keep it.
+    pub fn issue_tokens_for(
+        &self,
+        audience: &str,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+        grants: &Grants,
+    ) -> Result<(String, String), String> {
+        let tokens = self.issue_token_pair(
+            user,
+            user_uuid,
+            person_uuid,
+            grants,
+            &uuid::Uuid::new_v4(),
+            Some(audience),
+        )?;
+        self.emit(AuthEvent::issued("token_pair", user, user_uuid));
+        Ok(tokens)
+    }
+
+    /// Logs the user in on a device: same as `issue_tokens_with`, with a
+    /// session record listed by `sessions` and revocable remotely.
+    pub async fn start_session(
+        &self,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+        grants: &Grants,
+        device: &DeviceInfo,
+    ) -> HelixAuthResult<(String, String)> {
+        let now = Utc::now();
+        let session = Session {
+            id: uuid::Uuid::new_v4(),
+            user_uuid: *user_uuid,
+            device: device.label.clone(),
+            ip: device.ip.clone(),
+            user_agent: device.user_agent.clone(),
+            created_on: now,
+            last_seen_on: now,
+            revoked_on: None,
+        };
+        self.session_store()?.add_session(&session).await?;
+
+        let tokens = self
+            .issue_token_pair(user, user_uuid, person_uuid, grants, &session.id, None)
+            .map_err(HelixAuthError::TokenGeneration)?;
+        self.emit(AuthEvent::issued("token_pair", user, user_uuid));
+        Ok(tokens)
+    }
+
+    //Active sessions of the user, most recently seen first.
+    pub async fn sessions(&self, user_uuid: &uuid::Uuid) -> HelixAuthResult<Vec<Session>> {
+        Ok(self
+            .session_store()?
+            .get_sessions_by_user(user_uuid)
+            .await?)
+    }
+
+    //Remote logout: refreshing tokens of the session fails from now on.
+    pub async fn revoke_session(
+        &self,
+        user_uuid: &uuid::Uuid,
+        session_id: &uuid::Uuid,
+    ) -> HelixAuthResult<()> {
+        match self
+            .session_store()?
+            .revoke_session(session_id, user_uuid, &Utc::now())
+            .await?
+        {
             true => Ok(()),
-            false => Err(HelixAuthError::InvalidToken),
+            false => Err(HelixAuthError::NotFoundError),
         }
     }
 
-    pub fn get_claimer(req: &HttpRequest) -> Option<Claims> {
-        match req.headers().get("Authorization") {
-            Some(value) => match HelixAuth::get_token_data(&value.to_str().unwrap().to_string()) {
-                Ok(data) => Some(data),
-                Err(_) => None,
-            },
+    //Logs out every other device, `current` is `Claims::get_family` of the caller.
+    pub async fn revoke_other_sessions(
+        &self,
+        user_uuid: &uuid::Uuid,
+        current: &uuid::Uuid,
+    ) -> HelixAuthResult<u64> {
+        Ok(self
+            .session_store()?
+            .revoke_other_sessions(user_uuid, current, &Utc::now())
+            .await?)
+    }
+
+    /// Exchanges a refresh token for a new token pair.
+    ///
+    /// With a revocation store the refresh token is single use: it is consumed
+    /// here, and presenting it again revokes every token of its family.
+    pub async fn refresh(&self, token: &str) -> HelixAuthResult<(String, String)> {
+        self.refresh_from(token, &DeviceInfo::default()).await
+    }
+
+    //Same as `refresh`, recording the IP the session is now used from.
+    pub async fn refresh_from(
+        &self,
+        token: &str,
+        device: &DeviceInfo,
+    ) -> HelixAuthResult<(String, String)> {
+        match self.rotate(token, device).await {
+            Ok((claims, tokens)) => {
+                self.emit(AuthEvent::TokenRefreshed {
+                    user: claims.get_user().clone(),
+                    user_uuid: *claims.get_user_uuid(),
+                    family: claims.get_family().cloned(),
+                });
+                Ok(tokens)
+            }
+            Err(e) => {
+                if !matches!(e, HelixAuthError::Storage { .. }) {
+                    self.emit(AuthEvent::validation_failed(&e));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn rotate(
+        &self,
+        token: &str,
+        device: &DeviceInfo,
+    ) -> HelixAuthResult<(Claims, (String, String))> {
+        let claims = self.refresh_claims(token)?;
+        //Impersonation is never extended.
+        if claims.is_impersonated() {
+            return Err(HelixAuthError::InvalidToken);
+        }
+        let family = claims
+            .get_family()
+            .cloned()
+            .unwrap_or_else(uuid::Uuid::new_v4);
+
+        //Pairs issued without session, e.g. by `issue_tokens`, are not tracked.
+        let session = match &self.session_store {
+            Some(store) => store.get_session(&family).await?,
             None => None,
+        };
+        if let Some(session) = &session {
+            if session.is_revoked() || session.user_uuid != *claims.get_user_uuid() {
+                return Err(HelixAuthError::RevokedToken);
+            }
+        }
+
+        if let Some(store) = &self.revocation_store {
+            let jti = claims.get_jti().ok_or(HelixAuthError::InvalidToken)?;
+
+            if let Some(revoked_on) = store.get_user_revoked_on(claims.get_user_uuid()).await? {
+                if claims.issued_until(&revoked_on) {
+                    return Err(HelixAuthError::RevokedToken);
+                }
+            }
+            if store.is_family_revoked(&family).await? {
+                return Err(HelixAuthError::RevokedToken);
+            }
+
+            let expires_on = Utc
+                .timestamp_opt(claims.exp, 0)
+                .single()
+                .unwrap_or_else(Utc::now);
+            if !store
+                .consume(jti, &family, claims.get_user_uuid(), &expires_on)
+                .await?
+            {
+                store.revoke_family(&family, claims.get_user_uuid()).await?;
+                return Err(HelixAuthError::ReusedToken);
+            }
         }
+
+        let tokens = self
+            .issue_token_pair(
+                claims.get_user(),
+                claims.get_user_uuid(),
+                claims.get_person_uuid(),
+                &claims.get_grants(),
+                &family,
+                claims.get_audience(),
+            )
+            .map_err(HelixAuthError::TokenGeneration)?;
+        if let (Some(store), Some(_)) = (&self.session_store, session) {
+            store
+                .touch_session(&family, &Utc::now(), device.ip.as_deref())
+                .await?;
+        }
+        Ok((claims, tokens))
     }
 
-    pub fn generate_tokens(
+    /// Whether a valid token was revoked since it was issued.
+    ///
+    /// Its user, its family or its session was revoked or, for a refresh
+    /// token, it was already exchanged. Each check needs its store.
+    pub async fn is_revoked(&self, claims: &Claims) -> HelixAuthResult<bool> {
+        if let (Some(store), Some(family)) = (&self.session_store, claims.get_family()) {
+            if let Some(session) = store.get_session(family).await? {
+                if session.is_revoked() || session.user_uuid != *claims.get_user_uuid() {
+                    return Ok(true);
+                }
+            }
+        }
+        let store = match &self.revocation_store {
+            Some(store) => store,
+            None => return Ok(false),
+        };
+        if let Some(revoked_on) = store.get_user_revoked_on(claims.get_user_uuid()).await? {
+            if claims.issued_until(&revoked_on) {
+                return Ok(true);
+            }
+        }
+        if let Some(family) = claims.get_family() {
+            if store.is_family_revoked(family).await? {
+                return Ok(true);
+            }
+        }
+        match (claims.is_refresh(), claims.get_jti()) {
+            (true, Some(jti)) => Ok(store.is_consumed(jti).await?),
+            _ => Ok(false),
+        }
+    }
+
+    //Kills every session of the user: refresh tokens issued until now are rejected.
+    pub async fn revoke(&self, user_uuid: &uuid::Uuid) -> HelixAuthResult<()> {
+        match &self.revocation_store {
+            Some(store) => Ok(store.revoke_user(user_uuid, &Utc::now()).await?),
+            None => Err(HelixAuthError::MissingConfiguration(
+                "revocation store".to_owned(),
+            )),
+        }
+    }
+
+    /// Mints a short-lived token for the backend service `service`.
+    ///
+    /// The token has no user, `Claims::is_service` tells it apart, and carries
+    /// `scopes` only. `audience` is the called service, see `issue_tokens_for`.
+    /// There is no refresh token: the service mints a new one when it expires.
+    pub fn issue_service_token(
+        &self,
+        service: &str,
+        scopes: &[&str],
+        audience: Option<&str>,
+    ) -> HelixAuthResult<String> {
+        if service.trim().is_empty() {
+            return Err(HelixAuthError::InvalidConfiguration(
+                "service name must not be empty".to_owned(),
+            ));
+        }
+
+        let scopes: Vec<String> = scopes.iter().map(|s| (*s).to_owned()).collect();
+        let token = tokenizer::Tokenizer::new(self.keys())
+            .claims(
+                claims::get_service_token_claims(&self.config, service, &scopes)
+                    .with_audience(audience),
+            )
+            .generate()
+            .map_err(HelixAuthError::TokenGeneration)?;
+        self.emit(AuthEvent::issued("service", service, &uuid::Uuid::nil()));
+        Ok(token)
+    }
+
+    /// Issues an access token of `user` to the support admin `admin`.
+    ///
+    /// The token names the admin in its `act` claim, lives at most
+    /// `impersonation_token_lifetime` and comes without refresh token. It is
+    /// only handed out once the audit hook recorded it. Checking the admin is
+    /// allowed to impersonate is up to the caller, e.g. with a role guard.
+    pub async fn impersonate(
+        &self,
+        admin: &Claims,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+        grants: &Grants,
+        reason: &str,
+    ) -> HelixAuthResult<String> {
+        let audit = self.impersonation_audit.as_ref().ok_or_else(|| {
+            HelixAuthError::MissingConfiguration("impersonation audit".to_owned())
+        })?;
+        //No chained impersonation, services have no admin behind them.
+        if admin.is_impersonated() || admin.is_service() || admin.is_api_key() {
+            return Err(HelixAuthError::InsufficientPermission);
+        }
+
+        let mut claims = claims::get_access_token_claims(
+            &self.config,
+            user,
+            user_uuid,
+            person_uuid,
+            &uuid::Uuid::new_v4(),
+        )
+        .with_grants(grants);
+        claims.exp = claims.iat + self.config.impersonation_token_lifetime().num_seconds();
+        claims.fid = None;
+        claims.act = Some(Actor {
+            sub: *admin.get_user_uuid(),
+            user: admin.get_user().clone(),
+        });
+
+        let event = ImpersonationEvent {
+            jti: claims.get_jti().cloned().unwrap_or_default(),
+            actor: admin.get_user().clone(),
+            actor_uuid: *admin.get_user_uuid(),
+            user: user.to_owned(),
+            user_uuid: *user_uuid,
+            reason: reason.to_owned(),
+            issued_on: Utc
+                .timestamp_opt(claims.iat, 0)
+                .single()
+                .unwrap_or_else(Utc::now),
+            expires_on: Utc
+                .timestamp_opt(claims.exp, 0)
+                .single()
+                .unwrap_or_else(Utc::now),
+        };
+        let token = tokenizer::Tokenizer::new(self.keys())
+            .claims(claims)
+            .generate()
+            .map_err(HelixAuthError::TokenGeneration)?;
+        audit.record(&event).await?;
+        self.emit(AuthEvent::issued("impersonation", user, user_uuid));
+        Ok(token)
+    }
+
+    /// First step of a two-factor login, once the password is checked.
+    ///
+    /// The returned token is rejected everywhere but by `complete_mfa` and
+    /// `complete_mfa_with_recovery_code`, which exchange it for a token pair.
+    pub fn issue_mfa_token(
+        &self,
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+        grants: &Grants,
+    ) -> HelixAuthResult<String> {
+        let token = tokenizer::Tokenizer::new(self.keys())
+            .claims(
+                claims::get_mfa_token_claims(
+                    &self.config,
+                    user,
+                    user_uuid,
+                    person_uuid,
+                    &uuid::Uuid::new_v4(),
+                )
+                .with_grants(grants),
+            )
+            .generate()
+            .map_err(HelixAuthError::TokenGeneration)?;
+        self.emit(AuthEvent::issued("mfa", user, user_uuid));
+        Ok(token)
+    }
+
+    /// Exchanges the mfa token once `code` is checked against `totp`.
+    ///
+    /// `last_step` is the step of the last code accepted for this user: codes
+    /// of that step or an older one are refused. Store the returned step in
+    /// its place. Wrong codes count against the mfa token, which is burnt
+    /// after `mfa_max_attempts` of them.
+    pub async fn complete_mfa(
+        &self,
+        mfa_token: &str,
+        totp: &Totp,
+        last_step: Option<u64>,
+        code: &str,
+    ) -> HelixAuthResult<(String, String, u64)> {
+        let claims = self.mfa_claims(mfa_token).await?;
+        let step = match totp.verify_after(code, &Utc::now(), last_step) {
+            Some(step) => step,
+            None => return Err(self.mfa_failed(&claims).await),
+        };
+        let (access, refresh) = self.exchange_mfa_claims(claims).await?;
+        Ok((access, refresh, step))
+    }
+
+    //Persist the recovery codes afterwards, the matching one is consumed.
+    pub async fn complete_mfa_with_recovery_code(
+        &self,
+        mfa_token: &str,
+        codes: &mut RecoveryCodes,
+        code: &str,
+    ) -> HelixAuthResult<(String, String)> {
+        let claims = self.mfa_claims(mfa_token).await?;
+        if !codes.consume(code)? {
+            return Err(self.mfa_failed(&claims).await);
+        }
+        self.exchange_mfa_claims(claims).await
+    }
+
+    //Refuses mfa tokens burnt here or, with a revocation store, anywhere.
+    async fn mfa_claims(&self, mfa_token: &str) -> HelixAuthResult<Claims> {
+        let claims: Claims = tokenizer::Tokenizer::new(self.keys())
+            .validation(claims::get_mfa_token_validation(&self.config))
+            .validate(mfa_token)?;
+        let jti = claims.get_jti().ok_or(HelixAuthError::InvalidToken)?;
+        if let Some((failures, _)) = self.mfa_failures.lock().unwrap().get(jti) {
+            if *failures >= self.config.mfa_max_attempts() {
+                return Err(HelixAuthError::ReusedToken);
+            }
+        }
+        if let Some(store) = &self.revocation_store {
+            if store.is_consumed(jti).await? {
+                return Err(HelixAuthError::ReusedToken);
+            }
+        }
+        Ok(claims)
+    }
+
+    //Counts a wrong code, burning the mfa token past the last attempt. With a
+    //revocation store it is burnt for every instance.
+    async fn mfa_failed(&self, claims: &Claims) -> HelixAuthError {
+        let jti = match claims.get_jti() {
+            Some(jti) => *jti,
+            None => return HelixAuthError::InvalidToken,
+        };
+        let failures = {
+            let mut failures = self.mfa_failures.lock().unwrap();
+            let now = Utc::now().timestamp();
+            failures.retain(|_, (_, exp)| *exp >= now);
+            let entry = failures.entry(jti).or_insert((0, claims.exp));
+            entry.0 += 1;
+            entry.0
+        };
+
+        if failures >= self.config.mfa_max_attempts() {
+            if let Err(e) = self.consume_mfa_token(claims).await {
+                return e;
+            }
+        }
+        HelixAuthError::InvalidOneTimePassword
+    }
+
+    //False when the mfa token was already used or burnt.
+    async fn consume_mfa_token(&self, claims: &Claims) -> HelixAuthResult<bool> {
+        let store = match &self.revocation_store {
+            Some(store) => store,
+            None => return Ok(true),
+        };
+        let jti = claims.get_jti().ok_or(HelixAuthError::InvalidToken)?;
+        let family = claims
+            .get_family()
+            .cloned()
+            .unwrap_or_else(uuid::Uuid::new_v4);
+        let expires_on = Utc
+            .timestamp_opt(claims.exp, 0)
+            .single()
+            .unwrap_or_else(Utc::now);
+        Ok(store
+            .consume(jti, &family, claims.get_user_uuid(), &expires_on)
+            .await?)
+    }
+
+    async fn exchange_mfa_claims(&self, claims: Claims) -> HelixAuthResult<(String, String)> {
+        let family = claims
+            .get_family()
+            .cloned()
+            .unwrap_or_else(uuid::Uuid::new_v4);
+
+        //With a revocation store the mfa token is single use, like refresh tokens.
+        if !self.consume_mfa_token(&claims).await? {
+            return Err(HelixAuthError::ReusedToken);
+        }
+
+        let tokens = self
+            .issue_token_pair(
+                claims.get_user(),
+                claims.get_user_uuid(),
+                claims.get_person_uuid(),
+                &claims.get_grants(),
+                &family,
+                None,
+            )
+            .map_err(HelixAuthError::TokenGeneration)?;
+        self.emit(AuthEvent::issued(
+            "token_pair",
+            claims.get_user(),
+            claims.get_user_uuid(),
+        ));
+        Ok(tokens)
+    }
+
+    //Stores a key built with `ApiKey::generate`.
+    pub async fn create_api_key(&self, key: &ApiKey) -> HelixAuthResult<()> {
+        Ok(self.api_key_store()?.add_api_key(key).await?)
+    }
+
+    pub async fn api_keys(&self, user_uuid: &uuid::Uuid) -> HelixAuthResult<Vec<ApiKey>> {
+        Ok(self
+            .api_key_store()?
+            .get_api_keys_by_user(user_uuid)
+            .await?)
+    }
+
+    pub async fn revoke_api_key(&self, id: &uuid::Uuid) -> HelixAuthResult<()> {
+        match self
+            .api_key_store()?
+            .revoke_api_key(id, &Utc::now())
+            .await?
+        {
+            true => Ok(()),
+            false => Err(HelixAuthError::NotFoundError),
+        }
+    }
+
+    //Principal of an API key, shaped like the claims of an access token.
+    pub async fn api_key_claims(&self, key: &str) -> HelixAuthResult<Claims> {
+        let (id, secret) = api_key::parse(key)?;
+        let key = self
+            .api_key_store()?
+            .get_api_key(&id)
+            .await?
+            .ok_or(HelixAuthError::InvalidToken)?;
+
+        key.check(secret, &Utc::now())?;
+        Ok(claims::get_api_key_claims(&self.config, &key))
+    }
+
+    fn session_store(&self) -> HelixAuthResult<&Arc<dyn SessionStore>> {
+        self.session_store
+            .as_ref()
+            .ok_or_else(|| HelixAuthError::MissingConfiguration("session store".to_owned()))
+    }
+
+    fn api_key_store(&self) -> HelixAuthResult<&Arc<dyn ApiKeyStore>> {
+        self.api_key_store
+            .as_ref()
+            .ok_or_else(|| HelixAuthError::MissingConfiguration("api key store".to_owned()))
+    }
+
+    pub(crate) fn emit(&self, event: AuthEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.record(&event);
+        }
+    }
+
+    fn issue_token_pair(
+        &self,
         user: &str,
         user_uuid: &uuid::Uuid,
         person_uuid: &uuid::Uuid,
+        grants: &Grants,
+        family: &uuid::Uuid,
+        audience: Option<&str>,
     ) -> Result<(String, String), String> {
-        let api_auth_key = env::var("HELIX_API_AUTH_KEY").expect("API_AUTH_KEY not found.");
-
-        let tokenizer_access = tokenizer::Tokenizer::new(api_auth_key.to_string());
-        let result_access = tokenizer_access
-            .claims(claims::get_access_token_claims(
-                &user,
-                &user_uuid,
-                &person_uuid,
-            ))
+        let result_access = tokenizer::Tokenizer::new(self.keys())
+            .claims(
+                claims::get_access_token_claims(&self.config, user, user_uuid, person_uuid, family)
+                    .with_grants(grants)
+                    .with_audience(audience),
+            )
             .generate();
 
-        let tokenizer_refresh = tokenizer::Tokenizer::new(api_auth_key.to_string());
-        let result_refresh = tokenizer_refresh
-            .claims(claims::get_refresh_token_claims(
-                &user,
-                &user_uuid,
-                &person_uuid,
-            ))
+        let result_refresh = tokenizer::Tokenizer::new(self.keys())
+            .claims(
+                claims::get_refresh_token_claims(
+                    &self.config,
+                    user,
+                    user_uuid,
+                    person_uuid,
+                    family,
+                )
+                .with_grants(grants)
+                .with_audience(audience),
+            )
             .generate();
 
         match result_access {
@@ -60,34 +744,368 @@ impl HelixAuth {
                 Ok(r) => Ok((a, r)),
                 Err(_) => Err("Oops, an error occured.".to_owned()),
             },
-            Err(_) => (Err("Oops, an error occured.".to_owned())),
+            Err(_) => Err("Oops, an error occured.".to_owned()),
+        }
+    }
+
+    //Decodes the value of an `Authorization: Bearer <token>` header.
+    pub(crate) fn token_data(&self, header: &str) -> HelixAuthResult<Claims> {
+        match header.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                self.access_claims(token.trim())
+            }
+            _ => Err(HelixAuthError::MalformedToken),
         }
     }
 
-    fn get_token_data(token: &str) -> Result<Claims, String> {
-        let api_auth_key = env::var("HELIX_API_AUTH_KEY").expect("API_AUTH_KEY not found.");
-        let v: Vec<&str> = token.split(' ').collect();
-        let tokenizer = tokenizer::Tokenizer::new(api_auth_key.to_string());
-        tokenizer
-            .validation(claims::get_access_token_validation())
-            .validate(&v[1].to_string())
+    //Decodes a bare refresh token, without consuming it.
+    pub(crate) fn refresh_claims(&self, token: &str) -> HelixAuthResult<Claims> {
+        tokenizer::Tokenizer::new(self.keys())
+            .validation(claims::get_refresh_token_validation(&self.config))
+            .validate(token)
+    }
+
+    //Decodes a bare access token.
+    pub(crate) fn access_claims(&self, token: &str) -> HelixAuthResult<Claims> {
+        if let Some(provider) = &self.oidc_provider {
+            if oidc::unverified_issuer(token).as_deref() == Some(provider.issuer()) {
+                return provider.validate(token);
+            }
+        }
+
+        let claims = tokenizer::Tokenizer::new(self.keys())
+            .validation(claims::get_principal_validation(&self.config))
+            .validate(token)?;
+        match claims.is_access() {
+            true => Ok(claims),
+            false => Err(HelixAuthError::InvalidToken),
+        }
+    }
+
+    //Static API kept for existing services: the configuration is read from the
+    //environment on the first call and a missing or malformed value panics.
+    pub(crate) fn from_env_or_panic() -> &'static Arc<HelixAuth> {
+        lazy_static! {
+            static ref FROM_ENV: Arc<HelixAuth> =
+                Arc::new(HelixAuth::from_env().unwrap_or_else(|e| panic!("{}", e)));
+        }
+        &FROM_ENV
+    }
+
+    pub fn is_auth_token_valid(token: &str) -> HelixAuthResult<()> {
+        HelixAuth::from_env_or_panic().validate(token)
+    }
+
+    pub fn get_claimer(req: &HttpRequest) -> Option<Claims> {
+        HelixAuth::from_env_or_panic().claimer(req)
+    }
+
+    pub fn generate_tokens(
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+    ) -> Result<(String, String), String> {
+        HelixAuth::from_env_or_panic().issue_tokens(user, user_uuid, person_uuid)
+    }
+
+    pub fn generate_tokens_with(
+        user: &str,
+        user_uuid: &uuid::Uuid,
+        person_uuid: &uuid::Uuid,
+        grants: &Grants,
+    ) -> Result<(String, String), String> {
+        HelixAuth::from_env_or_panic().issue_tokens_with(user, user_uuid, person_uuid, grants)
     }
 
     pub fn refresh_tokens(token: &str) -> Result<(String, String), String> {
-        let api_auth_key = env::var("HELIX_API_AUTH_KEY").expect("API_AUTH_KEY not found.");
+        //Without revocation store the future resolves immediately.
+        futures::executor::block_on(HelixAuth::from_env_or_panic().refresh(token))
+            .map_err(|_| "Oops, an error occured.".to_owned())
+    }
+}
 
-        let tokenizer = tokenizer::Tokenizer::new(api_auth_key.to_string());
-        let result = tokenizer
-            .validation(claims::get_refresh_token_validation())
-            .validate(token);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::AuthKey;
+    use crate::storage::mem_revocation_imp::MemRevocationStore;
+    use crate::storage::mem_session_imp::MemSessionStore;
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use std::sync::Mutex;
 
-        match result {
-            Ok(claims) => HelixAuth::generate_tokens(
-                &claims.get_user().to_owned(),
-                claims.get_user_uuid(),
-                claims.get_person_uuid(),
-            ),
-            Err(_) => Err("Oops, an error occured.".to_owned()),
+    #[derive(Default)]
+    struct AuditLog(Mutex<Vec<ImpersonationEvent>>);
+
+    #[async_trait]
+    impl ImpersonationAudit for AuditLog {
+        async fn record(&self, event: &ImpersonationEvent) -> HelixAuthResult<()> {
+            self.0.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn auth() -> HelixAuth {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        HelixAuth::new(config).with_revocation_store(Arc::new(MemRevocationStore::new()))
+    }
+
+    #[test]
+    fn refresh_token_reuse_revokes_family() {
+        let auth = auth();
+        let user_uuid = uuid::Uuid::new_v4();
+        let (_, refresh) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        let (_, rotated) = block_on(auth.refresh(&refresh)).unwrap();
+        assert!(matches!(
+            block_on(auth.refresh(&refresh)),
+            Err(HelixAuthError::ReusedToken)
+        ));
+        assert!(matches!(
+            block_on(auth.refresh(&rotated)),
+            Err(HelixAuthError::RevokedToken)
+        ));
+    }
+
+    #[test]
+    fn revoke_rejects_existing_refresh_tokens() {
+        let auth = auth();
+        let user_uuid = uuid::Uuid::new_v4();
+        //Most likely within the second of the revocation, before and after it.
+        let (_, refresh) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+        block_on(auth.revoke(&user_uuid)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let (_, relogin) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        assert!(matches!(
+            block_on(auth.refresh(&refresh)),
+            Err(HelixAuthError::RevokedToken)
+        ));
+        assert!(block_on(auth.refresh(&relogin)).is_ok());
+    }
+
+    #[test]
+    fn grants_survive_validation_and_refresh() {
+        let auth = auth();
+        let user_uuid = uuid::Uuid::new_v4();
+        let grants = Grants::new()
+            .role("admin")
+            .scope("read:tracker")
+            .extra("tenant", serde_json::json!("acme"))
+            .unwrap();
+        let (_, refresh) = auth
+            .issue_tokens_with("user", &user_uuid, &user_uuid, &grants)
+            .unwrap();
+
+        let (access, _) = block_on(auth.refresh(&refresh)).unwrap();
+        let claims = auth.token_data(&format!("Bearer {}", access)).unwrap();
+        assert!(claims.has_role("admin"));
+        assert!(claims.has_scope("read:tracker"));
+        assert_eq!(Some(&serde_json::json!("acme")), claims.get_extra("tenant"));
+    }
+
+    #[test]
+    fn tokens_are_only_accepted_by_their_audience() {
+        let service = |audience: &str| {
+            let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+                .audience(audience)
+                .not_before(true)
+                .build()
+                .unwrap();
+            HelixAuth::new(config)
+        };
+        let (login, tracker, billing) = (service("login"), service("tracker"), service("billing"));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (_, refresh) = login
+            .issue_tokens_for("tracker", "user", &user_uuid, &user_uuid, &Grants::new())
+            .unwrap();
+
+        let (access, _) = block_on(login.refresh(&refresh)).unwrap();
+        let bearer = format!("Bearer {}", access);
+        let claims = tracker.token_data(&bearer).unwrap();
+        assert_eq!(Some("tracker"), claims.get_audience());
+        assert_eq!(Some(claims.iat), claims.nbf);
+        assert!(matches!(
+            billing.token_data(&bearer),
+            Err(HelixAuthError::InvalidAudience)
+        ));
+    }
+
+    #[test]
+    fn impersonation_is_audited_and_names_the_admin() {
+        let audit = Arc::new(AuditLog::default());
+        let auth = auth().with_impersonation_audit(audit.clone());
+        let (admin_uuid, user_uuid) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let (admin, _) = auth
+            .issue_tokens("admin", &admin_uuid, &admin_uuid)
+            .unwrap();
+        let admin = auth.token_data(&format!("Bearer {}", admin)).unwrap();
+
+        let token = block_on(auth.impersonate(
+            &admin,
+            "user",
+            &user_uuid,
+            &user_uuid,
+            &Grants::new(),
+            "ticket 42",
+        ))
+        .unwrap();
+        let claims = auth.token_data(&format!("Bearer {}", token)).unwrap();
+        assert_eq!("user", claims.get_user());
+        assert_eq!(Some(&admin_uuid), claims.get_actor().map(|a| &a.sub));
+        assert!(
+            claims.exp - claims.iat <= auth.config().impersonation_token_lifetime().num_seconds()
+        );
+
+        let events = audit.0.lock().unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(
+            ("admin", &user_uuid, claims.get_jti()),
+            (
+                events[0].actor.as_str(),
+                &events[0].user_uuid,
+                Some(&events[0].jti)
+            )
+        );
+        drop(events);
+
+        //No chained impersonation
+        assert!(matches!(
+            block_on(auth.impersonate(
+                &claims,
+                "other",
+                &admin_uuid,
+                &admin_uuid,
+                &Grants::new(),
+                ""
+            )),
+            Err(HelixAuthError::InsufficientPermission)
+        ));
+    }
+
+    #[test]
+    fn revoked_sessions_can_no_longer_refresh() {
+        let auth = auth().with_session_store(Arc::new(MemSessionStore::new()));
+        let user_uuid = uuid::Uuid::new_v4();
+        let login = |label: &str| {
+            let device = DeviceInfo {
+                label: Some(label.to_owned()),
+                ..DeviceInfo::default()
+            };
+            block_on(auth.start_session("user", &user_uuid, &user_uuid, &Grants::new(), &device))
+                .unwrap()
+        };
+        let (laptop, _) = login("laptop");
+        let (_, phone) = login("phone");
+        let (_, tablet) = login("tablet");
+        assert_eq!(3, block_on(auth.sessions(&user_uuid)).unwrap().len());
+
+        let current = auth.token_data(&format!("Bearer {}", laptop)).unwrap();
+        let revoked =
+            block_on(auth.revoke_other_sessions(&user_uuid, current.get_family().unwrap()));
+        assert_eq!(2, revoked.unwrap());
+        let sessions = block_on(auth.sessions(&user_uuid)).unwrap();
+        assert_eq!(
+            vec![Some("laptop".to_owned())],
+            sessions
+                .iter()
+                .map(|s| s.device.clone())
+                .collect::<Vec<_>>()
+        );
+        for token in &[phone, tablet] {
+            let claims = auth.refresh_claims(token).unwrap();
+            assert!(block_on(auth.is_revoked(&claims)).unwrap());
+            assert!(matches!(
+                block_on(auth.refresh(token)),
+                Err(HelixAuthError::RevokedToken)
+            ));
+        }
+        assert!(!block_on(auth.is_revoked(&current)).unwrap());
+    }
+
+    #[test]
+    fn mfa_token_is_only_exchanged_after_totp() {
+        let auth = auth();
+        let totp = Totp::generate();
+        let user_uuid = uuid::Uuid::new_v4();
+        let grants = Grants::new().role("admin");
+        let mfa_token = auth
+            .issue_mfa_token("user", &user_uuid, &user_uuid, &grants)
+            .unwrap();
+
+        assert!(auth.validate(&format!("Bearer {}", mfa_token)).is_err());
+        assert!(matches!(
+            block_on(auth.complete_mfa(&mfa_token, &totp, None, "000000x")),
+            Err(HelixAuthError::InvalidOneTimePassword)
+        ));
+
+        let code = totp.code_at(&Utc::now());
+        let (access, _, step) =
+            block_on(auth.complete_mfa(&mfa_token, &totp, None, &code)).unwrap();
+        let claims = auth.token_data(&format!("Bearer {}", access)).unwrap();
+        assert!(claims.has_role("admin"));
+        assert!(matches!(
+            block_on(auth.complete_mfa(&mfa_token, &totp, None, &code)),
+            Err(HelixAuthError::ReusedToken)
+        ));
+
+        //Same code on a new login: its step is already used.
+        let mfa_token = auth
+            .issue_mfa_token("user", &user_uuid, &user_uuid, &grants)
+            .unwrap();
+        assert!(matches!(
+            block_on(auth.complete_mfa(&mfa_token, &totp, Some(step), &code)),
+            Err(HelixAuthError::InvalidOneTimePassword)
+        ));
+    }
+
+    #[test]
+    fn mfa_token_is_burnt_after_max_attempts() {
+        let auth = auth();
+        let totp = Totp::generate();
+        let user_uuid = uuid::Uuid::new_v4();
+        let mfa_token = auth
+            .issue_mfa_token("user", &user_uuid, &user_uuid, &Grants::new())
+            .unwrap();
+
+        for _ in 0..auth.config().mfa_max_attempts() {
+            assert!(matches!(
+                block_on(auth.complete_mfa(&mfa_token, &totp, None, "wrong")),
+                Err(HelixAuthError::InvalidOneTimePassword)
+            ));
+        }
+        let code = totp.code_at(&Utc::now());
+        assert!(matches!(
+            block_on(auth.complete_mfa(&mfa_token, &totp, None, &code)),
+            Err(HelixAuthError::ReusedToken)
+        ));
+    }
+
+    #[test]
+    fn mfa_token_burnt_on_one_instance_is_refused_by_the_others() {
+        let store = Arc::new(MemRevocationStore::new());
+        let instance = || {
+            let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+                .build()
+                .unwrap();
+            HelixAuth::new(config).with_revocation_store(store.clone())
+        };
+        let (first, second) = (instance(), instance());
+        let totp = Totp::generate();
+        let user_uuid = uuid::Uuid::new_v4();
+        let mfa_token = first
+            .issue_mfa_token("user", &user_uuid, &user_uuid, &Grants::new())
+            .unwrap();
+
+        for _ in 0..first.config().mfa_max_attempts() {
+            assert!(block_on(first.complete_mfa(&mfa_token, &totp, None, "wrong")).is_err());
         }
+        let code = totp.code_at(&Utc::now());
+        assert!(matches!(
+            block_on(second.complete_mfa(&mfa_token, &totp, None, &code)),
+            Err(HelixAuthError::ReusedToken)
+        ));
     }
 }
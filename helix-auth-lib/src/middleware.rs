@@ -1,25 +1,124 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::task::{Context, Poll};
 
+use crate::cookie::CookieConfig;
+use crate::rate_limit::RateLimiter;
+use crate::storage::traits::Denylist;
+use crate::validator::{AuthRequest, RequestValidator, RequestValidatorBuilder};
 use crate::HelixAuth;
 use actix_service::{Service, Transform};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::{Error, HttpResponse};
-use futures::future::{ok, Either, Ready};
+use actix_web::http::Method;
+use actix_web::{Error, HttpMessage, ResponseError};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use std::sync::Arc;
 
+/// Actix adapter of the `RequestValidator`.
 pub struct AuthValidator {
-    exception_uri: Vec<String>,
+    validator: RequestValidator,
 }
 
 impl AuthValidator {
+    //Protects `/api`, except the exact `exception_uri` paths. Shares the
+    //environment configured `HelixAuth` of the legacy functions.
     pub fn new(exception_uri: Vec<String>) -> Self {
-        AuthValidator { exception_uri }
+        AuthValidator::with_auth(HelixAuth::from_env_or_panic().clone(), exception_uri)
+    }
+
+    pub fn with_auth(auth: Arc<HelixAuth>, exception_uri: Vec<String>) -> Self {
+        AuthValidator::from(RequestValidator::with_exceptions(auth, &exception_uri))
+    }
+
+    pub fn builder(auth: Arc<HelixAuth>) -> AuthValidatorBuilder {
+        AuthValidatorBuilder {
+            inner: RequestValidator::builder(auth),
+        }
+    }
+
+    //Rejects valid tokens whose `jti` or user is denied. Wrap remote
+    //denylists in a `CachedDenylist`, it is consulted on every request.
+    pub fn with_denylist(mut self, denylist: Arc<dyn Denylist>) -> Self {
+        self.validator.set_denylist(denylist);
+        self
+    }
+
+    //Also accepts the access token from a cookie, with CSRF checks.
+    pub fn with_cookies(mut self, cookies: CookieConfig) -> Self {
+        self.validator.set_cookies(cookies);
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.validator.set_rate_limiter(limiter);
+        self
+    }
+}
+
+impl From<RequestValidator> for AuthValidator {
+    fn from(validator: RequestValidator) -> Self {
+        AuthValidator { validator }
+    }
+}
+
+/// Route rules of an `AuthValidator`, see `RequestValidatorBuilder`.
+pub struct AuthValidatorBuilder {
+    inner: RequestValidatorBuilder,
+}
+
+impl AuthValidatorBuilder {
+    pub fn protect(self, prefix: &str) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.protect(prefix),
+        }
+    }
+
+    pub fn protect_segments(self, prefix: &str) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.protect_segments(prefix),
+        }
+    }
+
+    pub fn public(self, pattern: &str) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.public(pattern),
+        }
+    }
+
+    pub fn public_method(self, method: Method, pattern: &str) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.public_method(method, pattern),
+        }
+    }
+
+    pub fn denylist(self, denylist: Arc<dyn Denylist>) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.denylist(denylist),
+        }
+    }
+
+    pub fn cookies(self, cookies: CookieConfig) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.cookies(cookies),
+        }
+    }
+
+    pub fn rate_limiter(self, limiter: RateLimiter) -> Self {
+        AuthValidatorBuilder {
+            inner: self.inner.rate_limiter(limiter),
+        }
+    }
+
+    pub fn build(self) -> AuthValidator {
+        AuthValidator::from(self.inner.build())
     }
 }
 
 impl<S, B> Transform<S> for AuthValidator
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
+    B: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
@@ -30,74 +129,298 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(AuthValidatorMiddleware {
-            service,
-            exception_uri: self.exception_uri.to_vec(),
+            service: Rc::new(RefCell::new(service)),
+            validator: Rc::new(self.validator.clone()),
         })
     }
 }
 
 pub struct AuthValidatorMiddleware<S> {
-    service: S,
-    exception_uri: Vec<String>,
-}
-
-impl<S> AuthValidatorMiddleware<S> {
-    fn is_api_call(&self, uri: &str) -> bool {
-        uri.starts_with("/api")
-    }
-    fn is_exception_uri(&self, uri: &str) -> bool {
-        let search: String = uri.replace("//", "/");
-        self.exception_uri.contains(&search)
-    }
-    fn is_auth_token_valid(&self, token: &str) -> bool {
-        match HelixAuth::is_auth_token_valid(token) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
-    }
+    service: Rc<RefCell<S>>,
+    validator: Rc<RequestValidator>,
 }
 
 impl<S, B> Service for AuthValidatorMiddleware<S>
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
+    B: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
     type Error = Error;
-    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+        self.service.borrow_mut().poll_ready(cx)
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
-        // We only need to hook into the `start` for this middleware.
-
-        //Check if the route is excluded.
-        let uri = &req.uri().to_string();
-        if !self.is_api_call(uri) || self.is_exception_uri(uri) {
-            Either::Left(self.service.call(req))
-        } else {
-            //Valid Authorization header
-            match req.headers().get("Authorization") {
-                Some(value) => {
-                    if self.is_auth_token_valid(&value.to_str().unwrap().to_string()) {
-                        Either::Left(self.service.call(req))
-                    } else {
-                        //Auth NOT OK"
-                        Either::Right(ok(
-                            req.into_response(HttpResponse::Unauthorized().finish().into_body())
-                        ))
-                    }
-                }
-                None => {
-                    //NO Auth Token"
-                    Either::Right(ok(req.into_response(
-                        HttpResponse::ExpectationFailed().finish().into_body(),
-                    )))
+        let service = self.service.clone();
+        let validator = self.validator.clone();
+
+        async move {
+            match validator.validate(&auth_request(&req)).await {
+                //Decoded claims are available to guards and handlers down the chain.
+                Ok(Some(claims)) => {
+                    req.extensions_mut().insert(claims);
                 }
+                Ok(None) => {}
+                Err(e) => return Ok(req.into_response(e.error_response().into_body())),
             }
+
+            let fut = service.borrow_mut().call(req);
+            fut.await
         }
+        .boxed_local()
+    }
+}
+
+fn auth_request(req: &ServiceRequest) -> AuthRequest {
+    let mut request = AuthRequest::new(req.method().clone(), req.path())
+        .peer(req.peer_addr().map(|addr| addr.ip()));
+    for (name, value) in req.headers() {
+        request = request.header(name.as_str(), &String::from_utf8_lossy(value.as_bytes()));
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_key::ApiKey;
+    use crate::config::AuthConfig;
+    use crate::event::AuthEvent;
+    use crate::extractor::AuthenticatedUser;
+    use crate::keys::AuthKey;
+    use crate::keyset::KeySet;
+    use crate::rate_limit::{Backoff, TokenBucket};
+    use crate::storage::mem_api_key_imp::MemApiKeyStore;
+    use crate::storage::mem_auth_event_imp::MemAuthEventSink;
+    use crate::storage::mem_denylist_imp::MemDenylist;
+    use crate::storage::mem_rate_limit_imp::MemRateLimitStore;
+    use crate::storage::traits::DenylistKey;
+    use actix_web::{http::header, http::StatusCode, test, web, App, HttpResponse};
+    use chrono::prelude::*;
+
+    #[actix_rt::test]
+    async fn denied_user_gets_unauthorized() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let denylist = Arc::new(MemDenylist::new());
+        let user_uuid = uuid::Uuid::new_v4();
+        let (access, _) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    AuthValidator::with_auth(auth.clone(), vec![]).with_denylist(denylist.clone()),
+                )
+                .route("/api/me", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = || {
+            test::TestRequest::get()
+                .uri("/api/me")
+                .header("Authorization", format!("Bearer {}", access))
+                .to_request()
+        };
+
+        let response = test::call_service(&mut app, request()).await;
+        assert_eq!(StatusCode::OK, response.status());
+
+        denylist
+            .deny(
+                &DenylistKey::User(user_uuid),
+                &(Utc::now() + chrono::Duration::hours(1)),
+            )
+            .await
+            .unwrap();
+        let response = test::call_service(&mut app, request()).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    }
+
+    #[actix_rt::test]
+    async fn auth_events_are_recorded() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let events = Arc::new(MemAuthEventSink::new());
+        let auth = Arc::new(HelixAuth::new(config).with_event_sink(events.clone()));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (access, _) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthValidator::with_auth(auth, vec![]))
+                .route("/api/me", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for token in &[access.as_str(), "forged"] {
+            let request = test::TestRequest::get()
+                .uri("/api/me")
+                .header("Authorization", format!("Bearer {}", token))
+                .to_request();
+            test::call_service(&mut app, request).await;
+        }
+
+        let names: Vec<&str> = events.events().iter().map(AuthEvent::name).collect();
+        assert_eq!(
+            vec!["token_issued", "validation_failed", "request_rejected"],
+            names
+        );
+        assert!(matches!(
+            &events.events()[2],
+            AuthEvent::RequestRejected { path, reason, .. } if path == "/api/me" && reason == "malformed_token"
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn rate_limits_and_lockouts_answer_too_many_requests() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (access, _) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+        let limiter = RateLimiter::new(Arc::new(MemRateLimitStore::new()))
+            .anonymous(TokenBucket::per_minute(1, 1))
+            .authenticated(TokenBucket::per_minute(2, 1))
+            .backoff(Backoff::new(
+                2,
+                chrono::Duration::seconds(30),
+                chrono::Duration::minutes(5),
+            ));
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    AuthValidator::builder(auth)
+                        .public("/api/status")
+                        .rate_limiter(limiter)
+                        .build(),
+                )
+                .route("/api/me", web::get().to(HttpResponse::Ok))
+                .route("/api/status", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = |uri: &str, ip: &str, token: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .peer_addr(format!("{}:4000", ip).parse().unwrap())
+                .header("Authorization", format!("Bearer {}", token))
+                .to_request()
+        };
+        let mut statuses = Vec::new();
+        for (uri, ip, token) in &[
+            ("/api/status", "10.0.0.1", ""),
+            ("/api/status", "10.0.0.1", ""),
+            ("/api/status", "10.0.0.2", ""),
+            ("/api/me", "10.0.0.3", access.as_str()),
+            ("/api/me", "10.0.0.4", access.as_str()),
+            ("/api/me", "10.0.0.5", access.as_str()),
+            ("/api/me", "10.0.0.6", "forged"),
+            ("/api/me", "10.0.0.6", "forged"),
+        ] {
+            let response = test::call_service(&mut app, request(uri, ip, token)).await;
+            statuses.push(response.status().as_u16());
+        }
+        assert_eq!(vec![200, 429, 200, 200, 200, 429, 401, 401], statuses);
+
+        //Locked out, even with a valid token
+        let response = test::call_service(&mut app, request("/api/me", "10.0.0.6", &access)).await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+        assert_eq!("30", response.headers().get(header::RETRY_AFTER).unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn rejections_carry_a_bearer_challenge() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let user_uuid = uuid::Uuid::new_v4();
+        let mut claims = crate::claims::get_access_token_claims(
+            &config, "user", &user_uuid, &user_uuid, &user_uuid,
+        );
+        claims.exp = claims.iat - 60;
+        let expired = crate::tokenizer::Tokenizer::new(config.keys())
+            .claims(claims)
+            .generate()
+            .unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthValidator::with_auth(
+                    Arc::new(HelixAuth::new(config)),
+                    vec![],
+                ))
+                .route("/api/me", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/api/me").to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        assert_eq!(
+            "Bearer",
+            response.headers().get(header::WWW_AUTHENTICATE).unwrap()
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/api/me")
+            .header("Authorization", format!("Bearer {}", expired))
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        assert_eq!(
+            r#"Bearer error="invalid_token", error_description="Token expired""#,
+            response.headers().get(header::WWW_AUTHENTICATE).unwrap()
+        );
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!("expired_token", body["error"]);
+    }
+
+    async fn push_logs(user: AuthenticatedUser) -> HttpResponse {
+        match user.is_api_key() && user.has_scope("tracker:write") {
+            true => HttpResponse::Ok().finish(),
+            false => HttpResponse::Forbidden().finish(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn api_keys_authenticate_until_revoked() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth =
+            Arc::new(HelixAuth::new(config).with_api_key_store(Arc::new(MemApiKeyStore::new())));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (key, record) = ApiKey::generate("cron", "user", &user_uuid, &user_uuid);
+        let record = record.with_scopes(&["tracker:write"]);
+        auth.create_api_key(&record).await.unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AuthValidator::with_auth(auth.clone(), vec![]))
+                .route("/api/logs", web::post().to(push_logs)),
+        )
+        .await;
+        let request = |key: &str| {
+            test::TestRequest::post()
+                .uri("/api/logs")
+                .header("Authorization", format!("ApiKey {}", key))
+                .to_request()
+        };
+
+        let response = test::call_service(&mut app, request(&key)).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response = test::call_service(&mut app, request(&format!("{}x", key))).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+        auth.revoke_api_key(&record.id).await.unwrap();
+        let response = test::call_service(&mut app, request(&key)).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
     }
 }
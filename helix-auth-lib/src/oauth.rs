@@ -0,0 +1,931 @@
+//OAuth2 authorization code flow with PKCE (RFC 6749, RFC 7636) and token
+//introspection (RFC 7662), issuing the usual HelixAuth token pair. Backend
+//services get service tokens through the client credentials grant.
+use crate::claims::{Claims, Grants};
+use crate::error::*;
+use crate::extractor::AuthenticatedUser;
+use crate::password::constant_time_eq;
+use crate::storage::traits::{AuthorizationCodeStore, Denylist, DenylistKey, OAuthClientStore};
+use crate::HelixAuth;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::prelude::*;
+use chrono::Duration;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Registered third party application.
+///
+/// Confidential clients authenticate with a secret, stored hashed. Every
+/// client, confidential or not, must use PKCE.
+#[derive(Debug, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub name: String,
+    pub secret_hash: Option<Vec<u8>>,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl OAuthClient {
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+
+    fn check_secret(&self, secret: Option<&str>) -> bool {
+        match (&self.secret_hash, secret) {
+            (None, _) => true,
+            (Some(hash), Some(secret)) => constant_time_eq(hash, &sha256(secret)),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Code handed to the client by the authorization endpoint.
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode {
+    pub code: String,
+    pub client_id: String,
+    //As sent to the authorization endpoint, the token request must repeat it.
+    //`None` when it was left out for the single registered uri.
+    pub redirect_uri: Option<String>,
+    pub code_challenge: String,
+    pub user: String,
+    pub user_uuid: uuid::Uuid,
+    pub person_uuid: uuid::Uuid,
+    pub scopes: Vec<String>,
+    pub expires_on: DateTime<Utc>,
+}
+
+/// State shared by the OAuth2 handlers, see `OAuthServer::configure`.
+pub struct OAuthServer {
+    auth: Arc<HelixAuth>,
+    clients: Arc<dyn OAuthClientStore>,
+    codes: Arc<dyn AuthorizationCodeStore>,
+    denylist: Option<Arc<dyn Denylist>>,
+    code_lifetime: Duration,
+}
+
+impl OAuthServer {
+    pub fn new(
+        auth: Arc<HelixAuth>,
+        clients: Arc<dyn OAuthClientStore>,
+        codes: Arc<dyn AuthorizationCodeStore>,
+    ) -> Self {
+        OAuthServer {
+            auth,
+            clients,
+            codes,
+            denylist: None,
+            code_lifetime: Duration::minutes(1),
+        }
+    }
+
+    //Introspection reports denied tokens and users inactive, like the validator.
+    pub fn denylist(mut self, denylist: Arc<dyn Denylist>) -> Self {
+        self.denylist = Some(denylist);
+        self
+    }
+
+    pub fn code_lifetime(mut self, lifetime: Duration) -> Self {
+        self.code_lifetime = lifetime;
+        self
+    }
+
+    /// Registers a client, returning its secret when it is confidential.
+    ///
+    /// The secret is only known here, the store keeps its hash.
+    pub async fn register_client(
+        &self,
+        name: &str,
+        redirect_uris: &[&str],
+        scopes: &[&str],
+        confidential: bool,
+    ) -> HelixAuthResult<(OAuthClient, Option<String>)> {
+        if redirect_uris.is_empty() {
+            return Err(HelixAuthError::InvalidConfiguration(
+                "a client needs at least one redirect uri".to_owned(),
+            ));
+        }
+
+        let secret = match confidential {
+            true => Some(random_token()),
+            false => None,
+        };
+        let client = OAuthClient {
+            client_id: uuid::Uuid::new_v4().to_simple().to_string(),
+            name: name.to_owned(),
+            secret_hash: secret.as_deref().map(sha256),
+            redirect_uris: to_strings(redirect_uris),
+            scopes: to_strings(scopes),
+        };
+
+        self.clients.add_client(&client).await?;
+        Ok((client, secret))
+    }
+
+    /// Registers a backend service, allowed the client credentials grant only.
+    ///
+    /// Its service tokens are named after the client id, and restricted to
+    /// `scopes`. Returns the client and its secret.
+    pub async fn register_service(
+        &self,
+        name: &str,
+        scopes: &[&str],
+    ) -> HelixAuthResult<(OAuthClient, String)> {
+        let secret = random_token();
+        let client = OAuthClient {
+            client_id: uuid::Uuid::new_v4().to_simple().to_string(),
+            name: name.to_owned(),
+            secret_hash: Some(sha256(&secret)),
+            redirect_uris: Vec::new(),
+            scopes: to_strings(scopes),
+        };
+
+        self.clients.add_client(&client).await?;
+        Ok((client, secret))
+    }
+
+    /// Routes `/authorize`, `/token` and `/introspect`.
+    ///
+    /// The server must be registered as app data, and `/authorize` wrapped
+    /// by an `AuthValidator`: how the user logs in is up to the application.
+    ///
+    /// ```ignore
+    /// App::new()
+    ///     .app_data(web::Data::new(server))
+    ///     .wrap(AuthValidator::builder(auth).protect("/oauth/authorize").build())
+    ///     .service(web::scope("/oauth").configure(OAuthServer::configure))
+    /// ```
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        cfg.route("/authorize", web::get().to(authorize))
+            .route("/token", web::post().to(token))
+            .route("/introspect", web::post().to(introspect));
+    }
+
+    async fn is_revoked(&self, claims: &Claims) -> HelixAuthResult<bool> {
+        if self.auth.is_revoked(claims).await? {
+            return Ok(true);
+        }
+        if let Some(denylist) = &self.denylist {
+            let mut keys = vec![DenylistKey::User(*claims.get_user_uuid())];
+            if let Some(jti) = claims.get_jti() {
+                keys.push(DenylistKey::Token(*jti));
+            }
+            for key in &keys {
+                if denylist.is_denied(key).await? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    async fn authenticate_client(
+        &self,
+        req: &HttpRequest,
+        client_id: Option<&str>,
+        client_secret: Option<&str>,
+    ) -> Result<OAuthClient, HttpResponse> {
+        let basic = basic_credentials(req);
+        let (client_id, client_secret) = match &basic {
+            Some((id, secret)) => (Some(id.as_str()), Some(secret.as_str())),
+            None => (client_id, client_secret),
+        };
+
+        let client = match client_id {
+            Some(client_id) => self.clients.get_client(client_id).await,
+            None => Ok(None),
+        };
+        match client {
+            Ok(Some(client)) if client.check_secret(client_secret) => Ok(client),
+            Ok(_) => Err(oauth_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "client authentication failed",
+            )),
+            Err(e) => Err(HelixAuthError::from(e).error_response()),
+        }
+    }
+
+    fn token_response(&self, tokens: (String, String), scopes: &[String]) -> HttpResponse {
+        HttpResponse::Ok()
+            .header(header::CACHE_CONTROL, "no-store")
+            .header(header::PRAGMA, "no-cache")
+            .json(json!({
+                "access_token": tokens.0,
+                "token_type": "Bearer",
+                "expires_in": self.auth.config().access_token_lifetime().num_seconds(),
+                "refresh_token": tokens.1,
+                "scope": scopes.join(" "),
+            }))
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthorizeRequest {
+    response_type: String,
+    client_id: String,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+async fn authorize(
+    server: web::Data<OAuthServer>,
+    user: AuthenticatedUser,
+    query: web::Query<AuthorizeRequest>,
+) -> HttpResponse {
+    let query = query.into_inner();
+
+    //A scoped API key, a service or an impersonation token must not turn
+    //into a full user session.
+    if user.is_api_key() || user.is_service() || user.impersonator().is_some() {
+        return HelixAuthError::InsufficientPermission.error_response();
+    }
+
+    //Errors before the redirect uri is trusted must not redirect.
+    let client = match server.clients.get_client(&query.client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => {
+            return oauth_error(StatusCode::BAD_REQUEST, "invalid_client", "unknown client")
+        }
+        Err(e) => return HelixAuthError::from(e).error_response(),
+    };
+    let redirect_uri = match &query.redirect_uri {
+        Some(uri) if client.redirect_uris.contains(uri) => uri.clone(),
+        None if client.redirect_uris.len() == 1 => client.redirect_uris[0].clone(),
+        _ => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                "redirect_uri is not registered for this client",
+            )
+        }
+    };
+
+    let state = query.state.as_deref();
+    if query.response_type != "code" {
+        return redirect_error(&redirect_uri, state, "unsupported_response_type");
+    }
+    let code_challenge = match (query.code_challenge, query.code_challenge_method.as_deref()) {
+        (Some(challenge), Some("S256")) if !challenge.is_empty() => challenge,
+        _ => return redirect_error(&redirect_uri, state, "invalid_request"),
+    };
+    //A client never gets more than the signed-in user holds.
+    let allowed: Vec<String> = client
+        .scopes
+        .iter()
+        .filter(|s| user.get_scopes().contains(s))
+        .cloned()
+        .collect();
+    let scopes: Vec<String> = match &query.scope {
+        Some(scope) => scope.split_whitespace().map(str::to_owned).collect(),
+        None => allowed.clone(),
+    };
+    if scopes.iter().any(|s| !allowed.contains(s)) {
+        return redirect_error(&redirect_uri, state, "invalid_scope");
+    }
+
+    let code = AuthorizationCode {
+        code: random_token(),
+        client_id: client.client_id,
+        redirect_uri: query.redirect_uri,
+        code_challenge,
+        user: user.get_user().clone(),
+        user_uuid: *user.get_user_uuid(),
+        person_uuid: *user.get_person_uuid(),
+        scopes,
+        expires_on: Utc::now() + server.code_lifetime,
+    };
+    if let Err(e) = server.codes.add_code(&code).await {
+        return HelixAuthError::from(e).error_response();
+    }
+
+    redirect(
+        &redirect_uri,
+        &[("code", Some(code.code.as_str())), ("state", state)],
+    )
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+async fn token(
+    server: web::Data<OAuthServer>,
+    req: HttpRequest,
+    form: web::Form<TokenRequest>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let client = match server
+        .authenticate_client(
+            &req,
+            form.client_id.as_deref(),
+            form.client_secret.as_deref(),
+        )
+        .await
+    {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    match form.grant_type.as_str() {
+        "authorization_code" => {
+            let code = match form.code {
+                Some(code) => server.codes.take_code(&code).await,
+                None => Ok(None),
+            };
+            let code = match code {
+                Ok(Some(code)) => code,
+                Ok(None) => return invalid_grant("unknown or used code"),
+                Err(e) => return HelixAuthError::from(e).error_response(),
+            };
+
+            if code.client_id != client.client_id
+                || (code.redirect_uri.is_some() && form.redirect_uri != code.redirect_uri)
+                || code.expires_on <= Utc::now()
+            {
+                return invalid_grant("code not issued to this client or expired");
+            }
+            if !form
+                .code_verifier
+                .as_deref()
+                .is_some_and(|verifier| verify_pkce(verifier, &code.code_challenge))
+            {
+                return invalid_grant("PKCE verification failed");
+            }
+
+            let mut grants = Grants::new()
+                .extra("client_id", json!(client.client_id))
+                .expect("client_id is not a reserved claim");
+            grants.scopes = code.scopes.clone();
+            match server.auth.issue_tokens_with(
+                &code.user,
+                &code.user_uuid,
+                &code.person_uuid,
+                &grants,
+            ) {
+                Ok(tokens) => server.token_response(tokens, &code.scopes),
+                Err(e) => HelixAuthError::TokenGeneration(e).error_response(),
+            }
+        }
+        "refresh_token" => {
+            let token = form.refresh_token.unwrap_or_default();
+            let claims = match server.auth.refresh_claims(&token) {
+                Ok(claims) => claims,
+                Err(_) => return invalid_grant("invalid refresh token"),
+            };
+            if client_of(&claims) != Some(client.client_id.as_str()) {
+                return invalid_grant("refresh token not issued to this client");
+            }
+
+            match server.auth.refresh(&token).await {
+                Ok(tokens) => server.token_response(tokens, claims.get_scopes()),
+                Err(HelixAuthError::Storage { source }) => {
+                    HelixAuthError::Storage { source }.error_response()
+                }
+                Err(e) => invalid_grant(&e.to_string()),
+            }
+        }
+        //Service clients have no redirect uri, and no user.
+        "client_credentials" if client.is_confidential() && client.redirect_uris.is_empty() => {
+            let scopes: Vec<&str> = match &form.scope {
+                Some(scope) => scope.split_whitespace().collect(),
+                None => client.scopes.iter().map(String::as_str).collect(),
+            };
+            if scopes.iter().any(|s| !client.scopes.iter().any(|c| c == s)) {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_scope",
+                    "scope not allowed for this client",
+                );
+            }
+
+            match server
+                .auth
+                .issue_service_token(&client.client_id, &scopes, None)
+            {
+                Ok(token) => HttpResponse::Ok()
+                    .header(header::CACHE_CONTROL, "no-store")
+                    .header(header::PRAGMA, "no-cache")
+                    .json(json!({
+                        "access_token": token,
+                        "token_type": "Bearer",
+                        "expires_in": server.auth.config().service_token_lifetime().num_seconds(),
+                        "scope": scopes.join(" "),
+                    })),
+                Err(e) => e.error_response(),
+            }
+        }
+        "client_credentials" => oauth_error(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "client credentials are reserved to service clients",
+        ),
+        _ => oauth_error(
+            StatusCode::BAD_REQUEST,
+            "unsupported_grant_type",
+            "only authorization_code, refresh_token and client_credentials are supported",
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct IntrospectionRequest {
+    token: String,
+    token_type_hint: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+async fn introspect(
+    server: web::Data<OAuthServer>,
+    req: HttpRequest,
+    form: web::Form<IntrospectionRequest>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    match server
+        .authenticate_client(
+            &req,
+            form.client_id.as_deref(),
+            form.client_secret.as_deref(),
+        )
+        .await
+    {
+        //Only confidential clients may introspect.
+        Ok(client) if client.is_confidential() => {}
+        Ok(_) => {
+            return oauth_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "client authentication failed",
+            )
+        }
+        Err(response) => return response,
+    }
+
+    let access = || {
+        server
+            .auth
+            .access_claims(&form.token)
+            .map(|c| (c, "access_token"))
+    };
+    let refresh = || {
+        server
+            .auth
+            .refresh_claims(&form.token)
+            .map(|c| (c, "refresh_token"))
+    };
+    let claims = match form.token_type_hint.as_deref() {
+        Some("refresh_token") => refresh().or_else(|_| access()),
+        _ => access().or_else(|_| refresh()),
+    };
+    //Valid but revoked since: inactive too.
+    let claims = match claims {
+        Ok((claims, token_type)) => match server.is_revoked(&claims).await {
+            Ok(false) => Ok((claims, token_type)),
+            Ok(true) => Err(HelixAuthError::RevokedToken),
+            Err(e) => return e.error_response(),
+        },
+        Err(e) => Err(e),
+    };
+
+    let response = match claims {
+        Ok((claims, token_type)) => json!({
+            "active": true,
+            "scope": claims.get_scopes().join(" "),
+            "client_id": client_of(&claims),
+            "username": claims.get_user(),
+            "sub": match claims.get_service() {
+                Some(service) => json!(service),
+                None => json!(claims.get_user_uuid()),
+            },
+            "exp": claims.exp,
+            "iat": claims.iat,
+            "iss": claims.iss,
+            "aud": claims.aud,
+            "jti": claims.get_jti(),
+            "act": claims.get_actor(),
+            "token_type": token_type,
+        }),
+        Err(_) => json!({ "active": false }),
+    };
+    HttpResponse::Ok()
+        .header(header::CACHE_CONTROL, "no-store")
+        .json(response)
+}
+
+fn client_of(claims: &Claims) -> Option<&str> {
+    claims
+        .get_extra("client_id")
+        .and_then(|c| c.as_str())
+        .or_else(|| claims.get_service())
+}
+
+//RFC 7636 S256: BASE64URL(SHA256(verifier)) == challenge.
+fn verify_pkce(verifier: &str, challenge: &str) -> bool {
+    (43..=128).contains(&verifier.len())
+        && constant_time_eq(
+            URL_SAFE_NO_PAD.encode(sha256(verifier)).as_bytes(),
+            challenge.as_bytes(),
+        )
+}
+
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (id, secret) = decoded.split_once(':')?;
+    Some((id.to_owned(), secret.to_owned()))
+}
+
+fn redirect(uri: &str, params: &[(&str, Option<&str>)]) -> HttpResponse {
+    let params: Vec<(&str, &str)> = params
+        .iter()
+        .filter_map(|(k, v)| v.map(|v| (*k, v)))
+        .collect();
+    let query = serde_urlencoded::to_string(params).unwrap_or_default();
+    let separator = if uri.contains('?') { '&' } else { '?' };
+
+    HttpResponse::Found()
+        .header(header::LOCATION, format!("{}{}{}", uri, separator, query))
+        .finish()
+}
+
+fn redirect_error(uri: &str, state: Option<&str>, error: &str) -> HttpResponse {
+    redirect(uri, &[("error", Some(error)), ("state", state)])
+}
+
+fn invalid_grant(description: &str) -> HttpResponse {
+    oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", description)
+}
+
+fn oauth_error(status: StatusCode, error: &str, description: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .header(header::CACHE_CONTROL, "no-store")
+        .json(json!({ "error": error, "error_description": description }))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn sha256(value: &str) -> Vec<u8> {
+    Sha256::digest(value.as_bytes()).to_vec()
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| (*v).to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_key::ApiKey;
+    use crate::claims::Actor;
+    use crate::config::AuthConfig;
+    use crate::keys::AuthKey;
+    use crate::keyset::KeySet;
+    use crate::middleware::AuthValidator;
+    use crate::storage::mem_api_key_imp::MemApiKeyStore;
+    use crate::storage::mem_authorization_code_imp::MemAuthorizationCodeStore;
+    use crate::storage::mem_denylist_imp::MemDenylist;
+    use crate::storage::mem_oauth_client_imp::MemOAuthClientStore;
+    use crate::storage::mem_revocation_imp::MemRevocationStore;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn authorization_code_flow_with_pkce() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let server = OAuthServer::new(
+            auth.clone(),
+            Arc::new(MemOAuthClientStore::new()),
+            Arc::new(MemAuthorizationCodeStore::new()),
+        );
+        let (client, secret) = server
+            .register_client(
+                "tool",
+                &["https://tool.local/cb"],
+                &["tracker:read", "tracker:write"],
+                true,
+            )
+            .await
+            .unwrap();
+        let secret = secret.unwrap();
+        let user_uuid = uuid::Uuid::new_v4();
+        let (user_token, _) = auth
+            .issue_tokens_with(
+                "user",
+                &user_uuid,
+                &user_uuid,
+                &Grants::new().scope("tracker:read"),
+            )
+            .unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(server))
+                .wrap(
+                    AuthValidator::builder(auth)
+                        .protect("/oauth/authorize")
+                        .build(),
+                )
+                .service(web::scope("/oauth").configure(OAuthServer::configure)),
+        )
+        .await;
+
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = URL_SAFE_NO_PAD.encode(sha256(verifier));
+        let authorize = |params: &str| {
+            test::TestRequest::get()
+                .uri(&format!(
+                    "/oauth/authorize?response_type=code&client_id={}&{}&state=xyz\
+                     &code_challenge={}&code_challenge_method=S256",
+                    client.client_id, params, challenge
+                ))
+                .header("Authorization", format!("Bearer {}", user_token))
+                .to_request()
+        };
+        let code_of = |response: &ServiceResponse| {
+            let location = response.headers().get(header::LOCATION).unwrap();
+            let location = location.to_str().unwrap();
+            assert!(location.starts_with("https://tool.local/cb?code="));
+            assert!(location.ends_with("&state=xyz"));
+            location["https://tool.local/cb?code=".len()..]
+                .split('&')
+                .next()
+                .unwrap()
+                .to_owned()
+        };
+        let exchange = |code: &str, redirect_uri: Option<&str>, verifier: &str| {
+            let mut form = vec![
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("code_verifier", verifier),
+                ("client_id", client.client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ];
+            if let Some(redirect_uri) = redirect_uri {
+                form.push(("redirect_uri", redirect_uri));
+            }
+            test::TestRequest::post()
+                .uri("/oauth/token")
+                .set_form(&form)
+                .to_request()
+        };
+
+        //Registered for the client, but not held by the user
+        let response = test::call_service(&mut app, authorize("scope=tracker:write")).await;
+        let location = response.headers().get(header::LOCATION).unwrap();
+        assert!(location
+            .to_str()
+            .unwrap()
+            .starts_with("https://tool.local/cb?error=invalid_scope"));
+
+        //No redirect_uri at authorize: the token request may leave it out too
+        let response = test::call_service(&mut app, authorize("scope=tracker:read")).await;
+        assert_eq!(StatusCode::FOUND, response.status());
+        let code = code_of(&response);
+        let response = test::call_service(&mut app, exchange(&code, None, verifier)).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!("tracker:read", body["scope"]);
+        let access = body["access_token"].as_str().unwrap().to_owned();
+
+        //Codes are single use
+        let response = test::call_service(&mut app, exchange(&code, None, verifier)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+        //Sent at authorize: it must be repeated
+        let params = "scope=tracker:read&redirect_uri=https%3A%2F%2Ftool.local%2Fcb";
+        let response = test::call_service(&mut app, authorize(params)).await;
+        let code = code_of(&response);
+        let response = test::call_service(&mut app, exchange(&code, None, verifier)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let response = test::call_service(&mut app, authorize(params)).await;
+        let code = code_of(&response);
+        let response = test::call_service(
+            &mut app,
+            exchange(&code, Some("https://tool.local/cb"), verifier),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+
+        let request = test::TestRequest::post()
+            .uri("/oauth/introspect")
+            .header(
+                header::AUTHORIZATION,
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", client.client_id, secret))
+                ),
+            )
+            .set_form(&[("token", access.as_str())])
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, request).await;
+        assert_eq!(true, body["active"]);
+        assert_eq!(client.client_id, body["client_id"]);
+        assert_eq!("tracker:read", body["scope"]);
+    }
+
+    #[actix_rt::test]
+    async fn authorize_is_reserved_to_user_sessions() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth =
+            Arc::new(HelixAuth::new(config).with_api_key_store(Arc::new(MemApiKeyStore::new())));
+        let server = OAuthServer::new(
+            auth.clone(),
+            Arc::new(MemOAuthClientStore::new()),
+            Arc::new(MemAuthorizationCodeStore::new()),
+        );
+        let (client, _) = server
+            .register_client("tool", &["https://tool.local/cb"], &["tracker:read"], false)
+            .await
+            .unwrap();
+        let user_uuid = uuid::Uuid::new_v4();
+        let (key, record) = ApiKey::generate("cron", "user", &user_uuid, &user_uuid);
+        auth.create_api_key(&record.with_scopes(&["tracker:read"]))
+            .await
+            .unwrap();
+        let service = auth
+            .issue_service_token("billing", &["tracker:read"], None)
+            .unwrap();
+        let mut claims = crate::claims::get_access_token_claims(
+            auth.config(),
+            "user",
+            &user_uuid,
+            &user_uuid,
+            &user_uuid,
+        );
+        claims.act = Some(Actor {
+            sub: uuid::Uuid::new_v4(),
+            user: "admin".to_owned(),
+        });
+        let impersonation = crate::tokenizer::Tokenizer::new(auth.keys())
+            .claims(claims)
+            .generate()
+            .unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(server))
+                .wrap(
+                    AuthValidator::builder(auth)
+                        .protect("/oauth/authorize")
+                        .build(),
+                )
+                .service(web::scope("/oauth").configure(OAuthServer::configure)),
+        )
+        .await;
+        for authorization in &[
+            format!("ApiKey {}", key),
+            format!("Bearer {}", service),
+            format!("Bearer {}", impersonation),
+        ] {
+            let request = test::TestRequest::get()
+                .uri(&format!(
+                    "/oauth/authorize?response_type=code&client_id={}\
+                     &code_challenge=abc&code_challenge_method=S256",
+                    client.client_id
+                ))
+                .header("Authorization", authorization.as_str())
+                .to_request();
+            let response = test::call_service(&mut app, request).await;
+            assert_eq!(StatusCode::FORBIDDEN, response.status());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn client_credentials_mint_service_tokens() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(HelixAuth::new(config));
+        let server = OAuthServer::new(
+            auth.clone(),
+            Arc::new(MemOAuthClientStore::new()),
+            Arc::new(MemAuthorizationCodeStore::new()),
+        );
+        let (client, secret) = server
+            .register_service("billing", &["tracker:read"])
+            .await
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(server))
+                .service(web::scope("/oauth").configure(OAuthServer::configure)),
+        )
+        .await;
+
+        let grant = |scope: &str| {
+            test::TestRequest::post()
+                .uri("/oauth/token")
+                .set_form(&[
+                    ("grant_type", "client_credentials"),
+                    ("scope", scope),
+                    ("client_id", client.client_id.as_str()),
+                    ("client_secret", secret.as_str()),
+                ])
+                .to_request()
+        };
+        let response = test::call_service(&mut app, grant("tracker:write")).await;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+        let body: serde_json::Value =
+            test::read_response_json(&mut app, grant("tracker:read")).await;
+        assert!(body.get("refresh_token").is_none());
+        let claims = auth
+            .access_claims(body["access_token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(Some(client.client_id.as_str()), claims.get_service());
+        assert!(claims.get_user_uuid().is_nil());
+        assert!(claims.has_scope("tracker:read"));
+    }
+
+    #[actix_rt::test]
+    async fn introspection_reports_revoked_tokens_inactive() {
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = Arc::new(
+            HelixAuth::new(config).with_revocation_store(Arc::new(MemRevocationStore::new())),
+        );
+        let denylist = Arc::new(MemDenylist::new());
+        let server = OAuthServer::new(
+            auth.clone(),
+            Arc::new(MemOAuthClientStore::new()),
+            Arc::new(MemAuthorizationCodeStore::new()),
+        )
+        .denylist(denylist.clone());
+        let (client, secret) = server
+            .register_client("tool", &["https://tool.local/cb"], &[], true)
+            .await
+            .unwrap();
+        let credentials = STANDARD.encode(format!("{}:{}", client.client_id, secret.unwrap()));
+        let user_uuid = uuid::Uuid::new_v4();
+        let (access, rotated) = auth.issue_tokens("user", &user_uuid, &user_uuid).unwrap();
+        let (_, refresh) = auth.refresh(&rotated).await.unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(server))
+                .service(web::scope("/oauth").configure(OAuthServer::configure)),
+        )
+        .await;
+        let mut active = Vec::new();
+        for (token, deny) in &[(&rotated, false), (&refresh, false), (&access, true)] {
+            if *deny {
+                let claims = auth.access_claims(token).unwrap();
+                let until = Utc::now() + Duration::minutes(5);
+                denylist
+                    .deny(&DenylistKey::Token(*claims.get_jti().unwrap()), &until)
+                    .await
+                    .unwrap();
+            }
+            let request = test::TestRequest::post()
+                .uri("/oauth/introspect")
+                .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+                .set_form(&[("token", token.as_str())])
+                .to_request();
+            let body: serde_json::Value = test::read_response_json(&mut app, request).await;
+            active.push(body["active"].clone());
+        }
+        assert_eq!(vec![json!(false), json!(true), json!(false)], active);
+
+        auth.revoke(&user_uuid).await.unwrap();
+        let request = test::TestRequest::post()
+            .uri("/oauth/introspect")
+            .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+            .set_form(&[("token", refresh.as_str())])
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, request).await;
+        assert_eq!(false, body["active"]);
+    }
+}
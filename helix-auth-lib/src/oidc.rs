@@ -0,0 +1,336 @@
+use crate::claims::{Claims, OIDC_TOKEN_SUBJECT};
+use crate::error::*;
+use crate::keyset::KeySet;
+use crate::tokenizer::validation_error;
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{decode, decode_header, Validation};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// The members of an OpenID Connect discovery document we rely on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcDiscovery {
+    pub issuer: String,
+    pub jwks_uri: String,
+}
+
+/// Fetches discovery documents and JWKS, e.g. with the application's HTTP client.
+///
+/// Injecting it keeps helix-auth-lib free of an HTTP client, and lets tests
+/// and offline deployments serve a local stand-in.
+#[async_trait]
+pub trait OidcFetcher: Send + Sync {
+    //Body of a GET on `url`.
+    async fn fetch(&self, url: &str) -> HelixAuthResult<String>;
+}
+
+/// Which provider claims feed `Claims`.
+///
+/// `user` takes the first present claim, `email` only when `email_verified`
+/// is true. `roles` is a string or string array
+/// claim, scopes come from `scope` (space separated) or `scp`.
+#[derive(Debug, Clone)]
+pub struct ClaimMapping {
+    pub user: Vec<String>,
+    pub roles: String,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        ClaimMapping {
+            user: vec![
+                "email".to_owned(),
+                "preferred_username".to_owned(),
+                "sub".to_owned(),
+            ],
+            roles: "groups".to_owned(),
+        }
+    }
+}
+
+/// Validates access tokens issued by an external OpenID Connect provider.
+///
+/// Register it with `HelixAuth::with_oidc_provider`: tokens whose `iss` is the
+/// provider's are then validated here and mapped into `Claims`, so
+/// `AuthValidator`, guards and extractors work unchanged.
+pub struct OidcProvider {
+    discovery: OidcDiscovery,
+    keys: RwLock<KeySet>,
+    audiences: Vec<String>,
+    leeway: u64,
+    mapping: ClaimMapping,
+}
+
+impl OidcProvider {
+    pub fn new(discovery: OidcDiscovery, jwks: &str, audience: &str) -> HelixAuthResult<Self> {
+        Ok(OidcProvider {
+            discovery,
+            keys: RwLock::new(signing_keys(jwks)?),
+            audiences: vec![audience.to_owned()],
+            leeway: 0,
+            mapping: ClaimMapping::default(),
+        })
+    }
+
+    pub fn from_files<P: AsRef<Path>>(
+        discovery: P,
+        jwks: P,
+        audience: &str,
+    ) -> HelixAuthResult<Self> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .map_err(|e| HelixAuthError::Discovery(format!("{}: {}", path.display(), e)))
+        };
+        let discovery = serde_json::from_str(&read(discovery.as_ref())?)
+            .map_err(|e| HelixAuthError::Discovery(e.to_string()))?;
+        OidcProvider::new(discovery, &read(jwks.as_ref())?, audience)
+    }
+
+    //Loads `<issuer>/.well-known/openid-configuration`, then its JWKS.
+    pub async fn discover(
+        fetcher: &dyn OidcFetcher,
+        issuer: &str,
+        audience: &str,
+    ) -> HelixAuthResult<Self> {
+        let issuer = issuer.trim_end_matches('/');
+        let document = fetcher
+            .fetch(&format!("{}/.well-known/openid-configuration", issuer))
+            .await?;
+        let discovery: OidcDiscovery = serde_json::from_str(&document)
+            .map_err(|e| HelixAuthError::Discovery(e.to_string()))?;
+        if discovery.issuer.trim_end_matches('/') != issuer {
+            return Err(HelixAuthError::Discovery(format!(
+                "document issued for {}",
+                discovery.issuer
+            )));
+        }
+
+        let jwks = fetcher.fetch(&discovery.jwks_uri).await?;
+        OidcProvider::new(discovery, &jwks, audience)
+    }
+
+    //Reloads the JWKS, after the provider rotated its keys.
+    pub async fn refresh_keys(&self, fetcher: &dyn OidcFetcher) -> HelixAuthResult<()> {
+        let keys = signing_keys(&fetcher.fetch(&self.discovery.jwks_uri).await?)?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    //Accepts tokens for another client id too.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audiences.push(audience.to_owned());
+        self
+    }
+
+    //Clock skew tolerated on `exp` and `nbf`, in seconds.
+    pub fn leeway(mut self, seconds: u64) -> Self {
+        self.leeway = seconds;
+        self
+    }
+
+    pub fn mapping(mut self, mapping: ClaimMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.discovery.issuer
+    }
+
+    pub fn validate(&self, token: &str) -> HelixAuthResult<Claims> {
+        let header = decode_header(token).map_err(|_| HelixAuthError::MalformedToken)?;
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .verification_key(header.kid.as_deref())
+            .ok_or(HelixAuthError::InvalidToken)?;
+
+        let mut validation = Validation::new(key.algorithm());
+        validation.set_issuer(&[self.issuer()]);
+        validation.set_audience(&self.audiences);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.validate_nbf = true;
+        validation.leeway = self.leeway;
+
+        let data = decode::<Map<String, Value>>(token, key.decoding_key(), &validation)
+            .map_err(|e| validation_error(e.kind()))?;
+        self.map_claims(data.claims)
+    }
+
+    fn map_claims(&self, claims: Map<String, Value>) -> HelixAuthResult<Claims> {
+        let string = |name: &str| claims.get(name).and_then(Value::as_str);
+        let strings = |name: &str| -> Vec<String> {
+            match claims.get(name) {
+                Some(Value::String(s)) => s.split_whitespace().map(str::to_owned).collect(),
+                Some(Value::Array(values)) => values
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+
+        let subject = string("sub").ok_or(HelixAuthError::MalformedToken)?;
+        //Always scoped by issuer, so one provider can't claim another's users.
+        let user_uuid = uuid::Uuid::new_v5(
+            &uuid::Uuid::NAMESPACE_URL,
+            format!("{}#{}", self.issuer(), subject).as_bytes(),
+        );
+        let email_verified = claims.get("email_verified") == Some(&Value::Bool(true));
+        let user = self
+            .mapping
+            .user
+            .iter()
+            .filter(|name| name.as_str() != "email" || email_verified)
+            .find_map(|name| string(name))
+            .unwrap_or(subject);
+        let scopes = match claims.contains_key("scope") {
+            true => strings("scope"),
+            false => strings("scp"),
+        };
+
+        let mut extra = HashMap::new();
+        extra.insert("idp_sub".to_owned(), Value::from(subject));
+        if let Some(email) = claims.get("email") {
+            extra.insert("email".to_owned(), email.clone());
+        }
+
+        Ok(Claims {
+            iss: self.issuer().to_owned(),
+            sub: OIDC_TOKEN_SUBJECT.to_owned(),
+            aud: self.audiences.first().cloned(),
+            user: user.to_owned(),
+            user_uuid,
+            person_uuid: user_uuid,
+            exp: claims
+                .get("exp")
+                .and_then(Value::as_i64)
+                .unwrap_or_default(),
+            iat: claims
+                .get("iat")
+                .and_then(Value::as_i64)
+                .unwrap_or_default(),
+            iat_ms: None,
+            nbf: claims.get("nbf").and_then(Value::as_i64),
+            jti: string("jti").and_then(|jti| uuid::Uuid::parse_str(jti).ok()),
+            fid: None,
+            roles: strings(&self.mapping.roles),
+            scopes,
+            act: None,
+            extra,
+        })
+    }
+}
+
+//Issuer of a token, before any validation: only used to pick the validator.
+pub(crate) fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    payload.get("iss")?.as_str().map(str::to_owned)
+}
+
+//Providers publish encryption keys next to signing keys.
+fn signing_keys(jwks: &str) -> HelixAuthResult<KeySet> {
+    let mut document: Value =
+        serde_json::from_str(jwks).map_err(|e| HelixAuthError::Discovery(e.to_string()))?;
+    if let Some(keys) = document.get_mut("keys").and_then(Value::as_array_mut) {
+        keys.retain(|key| key.get("use").and_then(Value::as_str) != Some("enc"));
+    }
+    KeySet::from_jwks(&document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AuthConfig;
+    use crate::keys::AuthKey;
+    use crate::HelixAuth;
+    use chrono::Utc;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    struct LocalIdp;
+
+    #[async_trait]
+    impl OidcFetcher for LocalIdp {
+        async fn fetch(&self, url: &str) -> HelixAuthResult<String> {
+            match url {
+                "https://idp.local/.well-known/openid-configuration" => Ok(json!({
+                    "issuer": "https://idp.local",
+                    "jwks_uri": "https://idp.local/jwks",
+                })
+                .to_string()),
+                "https://idp.local/jwks" => Ok(json!({ "keys": [
+                    { "kty": "oct", "kid": "idp", "alg": "HS256", "k": "aWRwLXNlY3JldA" },
+                    { "kty": "oct", "kid": "enc", "use": "enc", "k": "ZW5j" },
+                ]})
+                .to_string()),
+                _ => Err(HelixAuthError::Discovery(url.to_owned())),
+            }
+        }
+    }
+
+    fn idp_token(claims: Value) -> String {
+        let header = Header {
+            kid: Some("idp".to_owned()),
+            ..Header::default()
+        };
+        encode(&header, &claims, &EncodingKey::from_secret(b"idp-secret")).unwrap()
+    }
+
+    #[test]
+    fn provider_tokens_are_validated_and_mapped() {
+        let provider = futures::executor::block_on(OidcProvider::discover(
+            &LocalIdp,
+            "https://idp.local/",
+            "helix",
+        ))
+        .unwrap();
+        let config = AuthConfig::builder("helix", KeySet::from(AuthKey::hmac(b"secret")))
+            .build()
+            .unwrap();
+        let auth = HelixAuth::new(config).with_oidc_provider(Arc::new(provider));
+        let now = Utc::now().timestamp();
+        let claims = |aud: &str, nbf: i64| {
+            json!({
+                "iss": "https://idp.local", "aud": aud, "sub": "u-42",
+                "exp": now + 60, "nbf": nbf, "iat": now,
+                "email": "jane@helix.local", "email_verified": true,
+                "groups": ["admin"], "scope": "openid tracker:read",
+            })
+        };
+
+        let mapped = auth
+            .token_data(&format!("Bearer {}", idp_token(claims("helix", now))))
+            .unwrap();
+        assert!(mapped.is_oidc());
+        assert_eq!("jane@helix.local", mapped.get_user());
+        assert!(mapped.has_role("admin"));
+        assert!(mapped.has_scope("tracker:read"));
+        assert_eq!(Some(&json!("u-42")), mapped.get_extra("idp_sub"));
+
+        let mut unverified = claims("helix", now);
+        unverified["email_verified"] = json!(false);
+        unverified["sub"] = json!(mapped.get_user_uuid().to_string());
+        let mapped_unverified = auth.access_claims(&idp_token(unverified)).unwrap();
+        assert_eq!(
+            &mapped.get_user_uuid().to_string(),
+            mapped_unverified.get_user()
+        );
+        assert_ne!(mapped.get_user_uuid(), mapped_unverified.get_user_uuid());
+
+        assert!(matches!(
+            auth.access_claims(&idp_token(claims("other", now))),
+            Err(HelixAuthError::InvalidAudience)
+        ));
+        assert!(auth
+            .access_claims(&idp_token(claims("helix", now + 3600)))
+            .is_err());
+    }
+}
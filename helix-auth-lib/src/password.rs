@@ -0,0 +1,183 @@
+use crate::error::*;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::Pbkdf2;
+use std::convert::TryFrom;
+
+/// Argon2id cost parameters, defaults follow the OWASP recommendation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Argon2Params {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Hashes passwords with Argon2id into PHC strings.
+///
+/// Verification also accepts legacy bcrypt (`$2a$`, `$2b$`, `$2y$`) and
+/// PBKDF2 PHC (`$pbkdf2-sha256$`) hashes: check `needs_rehash` after a
+/// successful login to migrate them.
+pub struct Passwords {
+    params: Argon2Params,
+}
+
+impl Default for Passwords {
+    fn default() -> Self {
+        Passwords::new(Argon2Params::default())
+    }
+}
+
+impl Passwords {
+    pub fn new(params: Argon2Params) -> Self {
+        Passwords { params }
+    }
+
+    pub fn hash(&self, password: &str) -> HelixAuthResult<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()?
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| HelixAuthError::PasswordHash(e.to_string()))
+    }
+
+    //Ok(false) on a wrong password, Err on an unreadable stored hash.
+    pub fn verify(&self, password: &str, stored: &str) -> HelixAuthResult<bool> {
+        if is_bcrypt(stored) {
+            return bcrypt::verify(password, stored)
+                .map_err(|e| HelixAuthError::PasswordHash(e.to_string()));
+        }
+
+        let hash = parse(stored)?;
+        let result = match hash.algorithm.as_str() {
+            "argon2id" | "argon2i" | "argon2d" => {
+                Argon2::default().verify_password(password.as_bytes(), &hash)
+            }
+            "pbkdf2" | "pbkdf2-sha256" | "pbkdf2-sha512" => {
+                Pbkdf2.verify_password(password.as_bytes(), &hash)
+            }
+            algorithm => {
+                return Err(HelixAuthError::PasswordHash(format!(
+                    "unsupported algorithm {}",
+                    algorithm
+                )))
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(HelixAuthError::PasswordHash(e.to_string())),
+        }
+    }
+
+    //True for legacy algorithms and Argon2id hashes with other parameters.
+    pub fn needs_rehash(&self, stored: &str) -> bool {
+        let hash = match parse(stored) {
+            Ok(hash) => hash,
+            Err(_) => return true,
+        };
+        if hash.algorithm.as_str() != "argon2id" || hash.version != Some(Version::V0x13.into()) {
+            return true;
+        }
+
+        match Params::try_from(&hash) {
+            Ok(params) => {
+                params.m_cost() != self.params.memory_kib
+                    || params.t_cost() != self.params.iterations
+                    || params.p_cost() != self.params.parallelism
+            }
+            Err(_) => true,
+        }
+    }
+
+    fn argon2(&self) -> HelixAuthResult<Argon2<'static>> {
+        let params = Params::new(
+            self.params.memory_kib,
+            self.params.iterations,
+            self.params.parallelism,
+            None,
+        )
+        .map_err(|e| HelixAuthError::InvalidConfiguration(e.to_string()))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+/// Compares secrets without leaking, through timing, where they differ.
+///
+/// The length is not secret: different lengths return early.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+fn is_bcrypt(stored: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| stored.starts_with(prefix))
+}
+
+fn parse(stored: &str) -> HelixAuthResult<PasswordHash<'_>> {
+    PasswordHash::new(stored).map_err(|e| HelixAuthError::PasswordHash(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pbkdf2::password_hash::PasswordHasher;
+
+    //Cheap parameters, the defaults make the tests slow.
+    fn passwords() -> Passwords {
+        Passwords::new(Argon2Params {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        })
+    }
+
+    #[test]
+    fn argon2id_round_trip_and_rehash() {
+        let passwords = passwords();
+        let hash = passwords.hash("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(passwords.verify("correct horse", &hash).unwrap());
+        assert!(!passwords.verify("battery staple", &hash).unwrap());
+        assert!(!passwords.needs_rehash(&hash));
+        assert!(Passwords::default().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn legacy_hashes_verify_and_need_rehash() {
+        let passwords = passwords();
+        let bcrypt = bcrypt::hash("correct horse", 4).unwrap();
+        let salt = SaltString::generate(&mut OsRng);
+        let params = pbkdf2::Params {
+            rounds: 1000,
+            output_length: 32,
+        };
+        let pbkdf2 = Pbkdf2
+            .hash_password_customized("correct horse".as_bytes(), None, None, params, &salt)
+            .unwrap()
+            .to_string();
+
+        for hash in &[bcrypt, pbkdf2] {
+            assert!(passwords.verify("correct horse", hash).unwrap());
+            assert!(!passwords.verify("battery staple", hash).unwrap());
+            assert!(passwords.needs_rehash(hash));
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use crate::claims::Claims;
+use crate::error::*;
+use crate::storage::traits::{RateLimitKey, RateLimitStore};
+use chrono::prelude::*;
+use chrono::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Token bucket: `burst` requests at once, then `per_second` on average.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucket {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl TokenBucket {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        TokenBucket { burst, per_second }
+    }
+
+    pub fn per_minute(burst: u32, per_minute: u32) -> Self {
+        TokenBucket::new(burst, f64::from(per_minute) / 60.0)
+    }
+
+    //Tokens of a bucket left with `tokens`, `elapsed` ago.
+    pub fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        let elapsed = elapsed.num_milliseconds().max(0) as f64 / 1000.0;
+        (tokens + elapsed * self.per_second).min(f64::from(self.burst))
+    }
+
+    //Wait until the next token, for a bucket holding `tokens` < 1.
+    pub fn wait(&self, tokens: f64) -> Duration {
+        match self.per_second > 0.0 {
+            true => {
+                Duration::milliseconds(((1.0 - tokens) / self.per_second * 1000.0).ceil() as i64)
+            }
+            false => Duration::days(1),
+        }
+    }
+}
+
+/// Lockout after repeated failed validations from one IP.
+///
+/// From the `threshold`th failure on, the IP is locked out for `base`,
+/// doubled on every further failure up to `max`. Failures are forgotten
+/// `max` after the last one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub threshold: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    pub fn new(threshold: u32, base: Duration, max: Duration) -> Self {
+        Backoff {
+            threshold,
+            base,
+            max,
+        }
+    }
+
+    pub fn lockout(&self, failures: u32) -> Option<Duration> {
+        if failures < self.threshold.max(1) {
+            return None;
+        }
+        //Past 2^20 any sensible `max` is reached anyway.
+        let doublings = (failures - self.threshold.max(1)).min(20);
+        Some((self.base * (1 << doublings)).min(self.max))
+    }
+}
+
+/// Rate limits applied by `AuthValidator`, see `AuthValidatorBuilder::rate_limiter`.
+///
+/// Public routes are limited per client IP, protected routes per user (or
+/// service). Limited requests get a 429 with `Retry-After`. Like the
+/// denylist, an unreachable store fails closed with a 503.
+///
+/// The client IP is the peer address, or behind `trusted_proxies` the last
+/// untrusted hop of `Forwarded` / `X-Forwarded-For`. Clients without a known
+/// IP, e.g. on a unix socket, are not limited per IP rather than sharing one
+/// bucket and one lockout.
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    anonymous: Option<TokenBucket>,
+    authenticated: Option<TokenBucket>,
+    backoff: Option<Backoff>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter {
+            store,
+            anonymous: None,
+            authenticated: None,
+            backoff: None,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    //Bucket of each client IP on public routes.
+    pub fn anonymous(mut self, bucket: TokenBucket) -> Self {
+        self.anonymous = Some(bucket);
+        self
+    }
+
+    //Bucket of each user on protected routes.
+    pub fn authenticated(mut self, bucket: TokenBucket) -> Self {
+        self.authenticated = Some(bucket);
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    //Reverse proxies whose forwarding headers are believed.
+    pub fn trusted_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Client IP of a request from `peer`, with the values of its `Forwarded`
+    /// and `X-Forwarded-For` headers.
+    ///
+    /// Forwarding headers are read from the right, proxies append to them: the
+    /// first hop which is not a trusted proxy is the client.
+    pub fn client_ip(
+        &self,
+        peer: Option<IpAddr>,
+        forwarded: &[&str],
+        forwarded_for: &[&str],
+    ) -> Option<IpAddr> {
+        let peer = peer?;
+        if !self.trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+
+        let hops: Vec<&str> = match forwarded.is_empty() {
+            false => forwarded
+                .iter()
+                .flat_map(|value| value.split(','))
+                .flat_map(|element| element.split(';'))
+                .filter_map(|pair| pair.trim().split_once('='))
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, value)| value)
+                .collect(),
+            true => forwarded_for
+                .iter()
+                .flat_map(|value| value.split(','))
+                .collect(),
+        };
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            //Obfuscated or garbage hops: the client is unknown.
+            client = parse_hop(hop)?;
+            if !self.trusted_proxies.contains(&client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    pub(crate) async fn check_anonymous(&self, ip: Option<IpAddr>) -> HelixAuthResult<()> {
+        match (&self.anonymous, ip) {
+            (Some(bucket), Some(ip)) => self.take(&ip_key(ip), bucket).await,
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) async fn check_authenticated(&self, claims: &Claims) -> HelixAuthResult<()> {
+        let key = match claims.get_service() {
+            Some(service) => RateLimitKey::Service(service.to_owned()),
+            None => RateLimitKey::User(*claims.get_user_uuid()),
+        };
+        match &self.authenticated {
+            Some(bucket) => self.take(&key, bucket).await,
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn check_lockout(&self, ip: Option<IpAddr>) -> HelixAuthResult<()> {
+        let ip = match (&self.backoff, ip) {
+            (Some(_), Some(ip)) => ip,
+            _ => return Ok(()),
+        };
+        let now = Utc::now();
+        match self.store.locked_until(&ip_key(ip), &now).await? {
+            Some(until) => Err(too_many_requests(until - now)),
+            None => Ok(()),
+        }
+    }
+
+    //Counts a failed validation, locking the IP out once past the threshold.
+    pub(crate) async fn failed(&self, ip: Option<IpAddr>) -> HelixAuthResult<()> {
+        let (backoff, ip) = match (&self.backoff, ip) {
+            (Some(backoff), Some(ip)) => (backoff, ip),
+            _ => return Ok(()),
+        };
+        let key = ip_key(ip);
+        let now = Utc::now();
+        let failures = self.store.record_failure(&key, &now, backoff.max).await?;
+        if let Some(lockout) = backoff.lockout(failures) {
+            self.store.lock(&key, &(now + lockout)).await?;
+        }
+        Ok(())
+    }
+
+    async fn take(&self, key: &RateLimitKey, bucket: &TokenBucket) -> HelixAuthResult<()> {
+        match self.store.take(key, bucket, &Utc::now()).await? {
+            Some(wait) => Err(too_many_requests(wait)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn ip_key(ip: IpAddr) -> RateLimitKey {
+    RateLimitKey::Ip(ip.to_string())
+}
+
+//`192.0.2.1`, `"[2001:db8::1]:4711"` or `10.0.0.1:80`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| hop.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+//Retry-After is in whole seconds, rounded up.
+fn too_many_requests(wait: Duration) -> HelixAuthError {
+    let millis = wait.num_milliseconds().max(1000) as u64;
+    HelixAuthError::TooManyRequests(millis.div_ceil(1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::mem_rate_limit_imp::MemRateLimitStore;
+    use crate::storage::pg_db_rate_limit_imp::PgDbRateLimitStore;
+    use std::env;
+
+    //Behaviour every store must share, on keys unique to the run.
+    async fn check_buckets(store: &dyn RateLimitStore) {
+        let now = Utc::now();
+        let key = |name: &str| RateLimitKey::Service(format!("{}-{}", name, uuid::Uuid::new_v4()));
+
+        let empty = TokenBucket::new(0, 1.0);
+        let key_empty = key("empty");
+        assert!(store
+            .take(&key_empty, &empty, &now)
+            .await
+            .unwrap()
+            .is_some());
+
+        let bucket = TokenBucket::new(2, 1.0);
+        let key_full = key("full");
+        let mut taken = Vec::new();
+        for _ in 0..3 {
+            taken.push(
+                store
+                    .take(&key_full, &bucket, &now)
+                    .await
+                    .unwrap()
+                    .is_none(),
+            );
+        }
+        assert_eq!(vec![true, true, false], taken);
+
+        let later = now + Duration::seconds(1);
+        assert!(store
+            .take(&key_full, &bucket, &later)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .take(&key_full, &bucket, &later)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let backoff = Backoff::new(3, Duration::seconds(10), Duration::minutes(1));
+        let lockouts: Vec<Option<i64>> = (1..=7)
+            .map(|failures| backoff.lockout(failures).map(|d| d.num_seconds()))
+            .collect();
+        assert_eq!(
+            vec![None, None, Some(10), Some(20), Some(40), Some(60), Some(60)],
+            lockouts
+        );
+    }
+
+    #[test]
+    fn client_ip_is_the_last_untrusted_hop() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let limiter =
+            RateLimiter::new(Arc::new(MemRateLimitStore::new())).trusted_proxies(vec![proxy]);
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        //Spoofed headers of a direct client are ignored.
+        assert_eq!(
+            ip("192.0.2.9"),
+            limiter.client_ip(ip("192.0.2.9"), &[], &["203.0.113.1"])
+        );
+        assert_eq!(
+            ip("203.0.113.7"),
+            limiter.client_ip(Some(proxy), &[], &["198.51.100.1, 203.0.113.7", "10.0.0.1"])
+        );
+        assert_eq!(
+            ip("2001:db8::1"),
+            limiter.client_ip(
+                Some(proxy),
+                &[r#"for=198.51.100.1, for="[2001:db8::1]:4711";proto=https"#],
+                &["203.0.113.7"]
+            )
+        );
+        assert_eq!(None, limiter.client_ip(Some(proxy), &["for=unknown"], &[]));
+        assert_eq!(None, limiter.client_ip(None, &[], &["203.0.113.7"]));
+    }
+
+    #[actix_rt::test]
+    async fn unknown_clients_are_never_locked_out() {
+        let limiter = RateLimiter::new(Arc::new(MemRateLimitStore::new()))
+            .anonymous(TokenBucket::new(0, 0.0))
+            .backoff(Backoff::new(1, Duration::minutes(1), Duration::minutes(1)));
+
+        limiter.failed(None).await.unwrap();
+        assert!(limiter.check_lockout(None).await.is_ok());
+        assert!(limiter.check_anonymous(None).await.is_ok());
+        let ip = "192.0.2.1".parse().ok();
+        limiter.failed(ip).await.unwrap();
+        assert!(limiter.check_lockout(ip).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn mem_store_buckets() {
+        check_buckets(&MemRateLimitStore::new()).await;
+    }
+
+    //Needs a database with `db/tables.sql`: `cargo test -- --ignored`.
+    #[actix_rt::test]
+    #[ignore]
+    async fn pg_store_buckets() {
+        let var = |name: &str| env::var(name).unwrap_or_else(|_| panic!("{} is not set", name));
+        let store = PgDbRateLimitStore::new(
+            var("HELIX_TEST_DB_NAME"),
+            var("HELIX_TEST_DB_HOST"),
+            var("HELIX_TEST_DB_PORT").parse().unwrap(),
+            var("HELIX_TEST_DB_USER"),
+            var("HELIX_TEST_DB_PASSWORD"),
+        );
+        check_buckets(&store).await;
+    }
+}
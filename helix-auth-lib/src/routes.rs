@@ -0,0 +1,174 @@
+use actix_web::http::Method;
+
+/// Path pattern matched segment by segment, the query string is ignored.
+///
+/// `{name}` (actix style) and `*` match exactly one segment, `**` matches any
+/// number of segments, and `*` inside a segment is a glob (`*.png`).
+#[derive(Debug, Clone)]
+pub struct RoutePattern {
+    segments: Vec<String>,
+}
+
+impl RoutePattern {
+    pub fn new(pattern: &str) -> Self {
+        RoutePattern {
+            segments: segments(pattern).map(str::to_owned).collect(),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path: Vec<&str> = segments(path).collect();
+        match_segments(&self.segments, &path)
+    }
+}
+
+//Raw prefixes match like `str::starts_with`: `/api` covers `/apidoc` too.
+#[derive(Debug, Clone)]
+enum Protected {
+    Prefix(String),
+    Segments(RoutePattern),
+}
+
+impl Protected {
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            //`//api` must not slip past `/api`.
+            Protected::Prefix(prefix) => {
+                let mut path = path.to_owned();
+                while path.contains("//") {
+                    path = path.replace("//", "/");
+                }
+                path.starts_with(prefix.as_str())
+            }
+            Protected::Segments(pattern) => pattern.matches(path),
+        }
+    }
+}
+
+/// Which requests `AuthValidator` must authenticate.
+#[derive(Debug, Clone, Default)]
+pub struct RouteRules {
+    protected: Vec<Protected>,
+    public: Vec<(Option<Method>, RoutePattern)>,
+}
+
+impl RouteRules {
+    //Historical behaviour: everything under `/api` but the exact exception uris.
+    pub fn with_exceptions(exception_uri: &[String]) -> Self {
+        let mut rules = RouteRules::default();
+        rules.protect("/api");
+        for uri in exception_uri {
+            rules.public(None, uri);
+        }
+        rules
+    }
+
+    pub fn protect(&mut self, prefix: &str) {
+        self.protected.push(Protected::Prefix(prefix.to_owned()));
+    }
+
+    //Whole segments only: `/api` covers `/api/me`, not `/apidoc`.
+    pub fn protect_segments(&mut self, prefix: &str) {
+        let mut pattern = RoutePattern::new(prefix);
+        pattern.segments.push("**".to_owned());
+        self.protected.push(Protected::Segments(pattern));
+    }
+
+    pub fn public(&mut self, method: Option<Method>, pattern: &str) {
+        self.public.push((method, RoutePattern::new(pattern)));
+    }
+
+    pub fn has_protected(&self) -> bool {
+        !self.protected.is_empty()
+    }
+
+    pub fn is_protected(&self, method: &Method, path: &str) -> bool {
+        let path = path.split('?').next().unwrap_or_default();
+
+        self.protected.iter().any(|p| p.matches(path))
+            && !self
+                .public
+                .iter()
+                .any(|(m, p)| !matches!(m, Some(m) if m != method) && p.matches(path))
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|s| !s.is_empty())
+}
+
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => match_segment(first, segment) && match_segments(rest, path),
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &str, segment: &str) -> bool {
+    if pattern.starts_with('{') && pattern.ends_with('}') {
+        return true;
+    }
+
+    //Glob: the parts between `*` must appear in order.
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == segment;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if segment.len() < first.len() + last.len()
+        || !segment.starts_with(first)
+        || !segment.ends_with(last)
+    {
+        return false;
+    }
+    let mut rest = &segment[first.len()..segment.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_match_patterns_methods_and_ignore_query() {
+        let mut rules = RouteRules::default();
+        rules.protect("/api");
+        rules.public(None, "/api/public/{id}");
+        rules.public(None, "/api/assets/**/*.png");
+        rules.public(Some(Method::GET), "/api/items/*");
+
+        assert!(!rules.is_protected(&Method::GET, "/health"));
+        assert!(rules.is_protected(&Method::GET, "/apidoc"));
+        assert!(rules.is_protected(&Method::GET, "/api-v2/me"));
+        assert!(rules.is_protected(&Method::GET, "//api/me"));
+        assert!(rules.is_protected(&Method::GET, "/api/me"));
+        assert!(!rules.is_protected(&Method::POST, "/api/public/42?lang=fr"));
+        assert!(rules.is_protected(&Method::GET, "/api/public/42/edit"));
+        assert!(!rules.is_protected(&Method::GET, "/api/assets/a/b/logo.png"));
+        assert!(rules.is_protected(&Method::GET, "/api/assets/a/logo.svg"));
+        assert!(!rules.is_protected(&Method::GET, "//api/items/42"));
+        assert!(rules.is_protected(&Method::POST, "/api/items/42"));
+
+        let mut rules = RouteRules::default();
+        rules.protect_segments("/admin");
+        assert!(rules.is_protected(&Method::GET, "/admin/users?page=2"));
+        assert!(!rules.is_protected(&Method::GET, "/administrator"));
+    }
+}
@@ -0,0 +1,49 @@
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use chrono::prelude::*;
+
+/// Login of a user on one device: every token pair refreshed from it.
+///
+/// Its id is the token family, see `Claims::get_family`. `last_seen_on` and
+/// `ip` are updated on every refresh.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: uuid::Uuid,
+    pub user_uuid: uuid::Uuid,
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_on: DateTime<Utc>,
+    pub last_seen_on: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_on: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_on.is_some()
+    }
+}
+
+/// Where a session is used from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceInfo {
+    pub label: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl DeviceInfo {
+    //Peer address and `User-Agent` of the request, with the label the client chose.
+    pub fn from_request(req: &HttpRequest, label: Option<&str>) -> Self {
+        DeviceInfo {
+            label: label.map(str::to_owned),
+            ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        }
+    }
+}
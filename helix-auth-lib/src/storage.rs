@@ -0,0 +1,16 @@
+pub mod cached_denylist_imp;
+pub mod error;
+pub mod mem_api_key_imp;
+pub mod mem_auth_event_imp;
+pub mod mem_authorization_code_imp;
+pub mod mem_denylist_imp;
+pub mod mem_oauth_client_imp;
+pub mod mem_rate_limit_imp;
+pub mod mem_revocation_imp;
+pub mod mem_session_imp;
+pub mod pg_db_api_key_imp;
+pub mod pg_db_auth_event_imp;
+pub mod pg_db_rate_limit_imp;
+pub mod pg_db_revocation_imp;
+pub mod pg_db_session_imp;
+pub mod traits;
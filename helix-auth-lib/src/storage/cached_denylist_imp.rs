@@ -0,0 +1,68 @@
+use crate::storage::error::*;
+use crate::storage::traits::{Denylist, DenylistKey};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Caches the answers of another denylist for a bounded time.
+///
+/// The middleware asks on every request, so a remote denylist is only hit once
+/// per key and TTL. A key denied through this cache is visible immediately on
+/// this instance; other instances see it once their cached answer expires.
+pub struct CachedDenylist {
+    inner: Arc<dyn Denylist>,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<DenylistKey, (bool, Instant)>>,
+}
+
+impl CachedDenylist {
+    pub fn new(inner: Arc<dyn Denylist>, ttl: Duration, max_entries: usize) -> Self {
+        CachedDenylist {
+            inner,
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, key: &DenylistKey) -> Option<bool> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((denied, cached_on)) if cached_on.elapsed() < self.ttl => Some(*denied),
+            _ => None,
+        }
+    }
+
+    fn store(&self, key: &DenylistKey, denied: bool) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries.retain(|_, (_, cached_on)| cached_on.elapsed() < ttl);
+            if entries.len() >= self.max_entries {
+                entries.clear();
+            }
+        }
+        entries.insert(*key, (denied, Instant::now()));
+    }
+}
+
+#[async_trait]
+impl Denylist for CachedDenylist {
+    async fn deny(&self, key: &DenylistKey, until: &DateTime<Utc>) -> StorageResult<()> {
+        self.inner.deny(key, until).await?;
+        self.store(key, true);
+        Ok(())
+    }
+
+    async fn is_denied(&self, key: &DenylistKey) -> StorageResult<bool> {
+        if let Some(denied) = self.cached(key) {
+            return Ok(denied);
+        }
+        let denied = self.inner.is_denied(key).await?;
+        self.store(key, denied);
+        Ok(denied)
+    }
+}
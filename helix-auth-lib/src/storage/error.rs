@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+//Define the possible errors
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Creation impossible")]
+    CreationImpossible,
+    #[error("Tokio Postgres error: {source}")]
+    TokioPostGres {
+        #[from]
+        source: tokio_postgres::Error,
+    },
+    #[error("Pool error: {source}")]
+    Pool {
+        #[from]
+        source: deadpool_postgres::PoolError,
+    },
+}
+
+//Define a generic error type to simplify return.
+pub type StorageResult<T> = std::result::Result<T, StorageError>;
@@ -0,0 +1,58 @@
+use crate::api_key::ApiKey;
+use crate::storage::error::*;
+use crate::storage::traits::ApiKeyStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+//Single process store, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemApiKeyStore {
+    keys: Mutex<HashMap<uuid::Uuid, ApiKey>>,
+}
+
+impl MemApiKeyStore {
+    pub fn new() -> Self {
+        MemApiKeyStore::default()
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for MemApiKeyStore {
+    async fn add_api_key(&self, key: &ApiKey) -> StorageResult<()> {
+        self.keys.lock().unwrap().insert(key.id, key.clone());
+        Ok(())
+    }
+
+    async fn get_api_key(&self, id: &uuid::Uuid) -> StorageResult<Option<ApiKey>> {
+        Ok(self.keys.lock().unwrap().get(id).cloned())
+    }
+
+    async fn get_api_keys_by_user(&self, user_uuid: &uuid::Uuid) -> StorageResult<Vec<ApiKey>> {
+        let mut keys: Vec<ApiKey> = self
+            .keys
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|k| k.user_uuid == *user_uuid)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.created_on);
+        Ok(keys)
+    }
+
+    async fn revoke_api_key(
+        &self,
+        id: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<bool> {
+        match self.keys.lock().unwrap().get_mut(id) {
+            Some(key) => {
+                key.revoked_on.get_or_insert(*revoked_on);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
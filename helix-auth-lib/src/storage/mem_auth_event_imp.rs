@@ -0,0 +1,24 @@
+use crate::event::{AuthEvent, AuthEventSink};
+use std::sync::Mutex;
+
+//Keeps every event in memory, for tests.
+#[derive(Default)]
+pub struct MemAuthEventSink {
+    events: Mutex<Vec<AuthEvent>>,
+}
+
+impl MemAuthEventSink {
+    pub fn new() -> Self {
+        MemAuthEventSink::default()
+    }
+
+    pub fn events(&self) -> Vec<AuthEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl AuthEventSink for MemAuthEventSink {
+    fn record(&self, event: &AuthEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
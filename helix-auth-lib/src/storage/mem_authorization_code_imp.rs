@@ -0,0 +1,35 @@
+use crate::oauth::AuthorizationCode;
+use crate::storage::error::*;
+use crate::storage::traits::AuthorizationCodeStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+//Single process store, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemAuthorizationCodeStore {
+    codes: Mutex<HashMap<String, AuthorizationCode>>,
+}
+
+impl MemAuthorizationCodeStore {
+    pub fn new() -> Self {
+        MemAuthorizationCodeStore::default()
+    }
+}
+
+#[async_trait]
+impl AuthorizationCodeStore for MemAuthorizationCodeStore {
+    async fn add_code(&self, code: &AuthorizationCode) -> StorageResult<()> {
+        let mut codes = self.codes.lock().unwrap();
+        let now = Utc::now();
+        codes.retain(|_, code| code.expires_on > now);
+
+        codes.insert(code.code.clone(), code.clone());
+        Ok(())
+    }
+
+    async fn take_code(&self, code: &str) -> StorageResult<Option<AuthorizationCode>> {
+        Ok(self.codes.lock().unwrap().remove(code))
+    }
+}
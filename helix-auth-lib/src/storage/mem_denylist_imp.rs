@@ -0,0 +1,35 @@
+use crate::storage::error::*;
+use crate::storage::traits::{Denylist, DenylistKey};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct MemDenylist {
+    entries: Mutex<HashMap<DenylistKey, DateTime<Utc>>>,
+}
+
+impl MemDenylist {
+    pub fn new() -> Self {
+        MemDenylist::default()
+    }
+}
+
+#[async_trait]
+impl Denylist for MemDenylist {
+    async fn deny(&self, key: &DenylistKey, until: &DateTime<Utc>) -> StorageResult<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Utc::now();
+        entries.retain(|_, until| *until > now);
+        entries.insert(*key, *until);
+        Ok(())
+    }
+
+    async fn is_denied(&self, key: &DenylistKey) -> StorageResult<bool> {
+        match self.entries.lock().unwrap().get(key) {
+            Some(until) => Ok(*until > Utc::now()),
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::oauth::OAuthClient;
+use crate::storage::error::*;
+use crate::storage::traits::OAuthClientStore;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+//Single process store, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemOAuthClientStore {
+    clients: Mutex<HashMap<String, OAuthClient>>,
+}
+
+impl MemOAuthClientStore {
+    pub fn new() -> Self {
+        MemOAuthClientStore::default()
+    }
+}
+
+#[async_trait]
+impl OAuthClientStore for MemOAuthClientStore {
+    async fn add_client(&self, client: &OAuthClient) -> StorageResult<()> {
+        self.clients
+            .lock()
+            .unwrap()
+            .insert(client.client_id.clone(), client.clone());
+        Ok(())
+    }
+
+    async fn get_client(&self, client_id: &str) -> StorageResult<Option<OAuthClient>> {
+        Ok(self.clients.lock().unwrap().get(client_id).cloned())
+    }
+}
@@ -0,0 +1,112 @@
+use crate::rate_limit::TokenBucket;
+use crate::storage::error::*;
+use crate::storage::traits::{RateLimitKey, RateLimitStore};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+//Entries kept before idle ones are dropped.
+const MAX_ENTRIES: usize = 4096;
+
+struct Failures {
+    count: u32,
+    last_failure_on: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+//Single process store, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemRateLimitStore {
+    buckets: Mutex<HashMap<RateLimitKey, (f64, DateTime<Utc>)>>,
+    failures: Mutex<HashMap<RateLimitKey, Failures>>,
+}
+
+impl MemRateLimitStore {
+    pub fn new() -> Self {
+        MemRateLimitStore::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemRateLimitStore {
+    async fn take(
+        &self,
+        key: &RateLimitKey,
+        bucket: &TokenBucket,
+        now: &DateTime<Utc>,
+    ) -> StorageResult<Option<Duration>> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_ENTRIES {
+            //A full bucket is the same as no bucket. Other kinds of keys have
+            //other buckets.
+            let kind = std::mem::discriminant(key);
+            buckets.retain(|k, (tokens, updated_on)| {
+                std::mem::discriminant(k) != kind
+                    || bucket.refill(*tokens, *now - *updated_on) < f64::from(bucket.burst)
+            });
+        }
+
+        let (tokens, updated_on) = buckets
+            .entry(key.clone())
+            .or_insert((f64::from(bucket.burst), *now));
+        let refilled = bucket.refill(*tokens, *now - *updated_on);
+        *updated_on = *now;
+        match refilled >= 1.0 {
+            true => {
+                *tokens = refilled - 1.0;
+                Ok(None)
+            }
+            false => {
+                *tokens = refilled;
+                Ok(Some(bucket.wait(refilled)))
+            }
+        }
+    }
+
+    async fn record_failure(
+        &self,
+        key: &RateLimitKey,
+        now: &DateTime<Utc>,
+        forget_after: Duration,
+    ) -> StorageResult<u32> {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_ENTRIES {
+            failures.retain(|_, f| f.last_failure_on + forget_after > *now);
+        }
+
+        let entry = failures.entry(key.clone()).or_insert(Failures {
+            count: 0,
+            last_failure_on: *now,
+            locked_until: None,
+        });
+        if entry.last_failure_on + forget_after < *now {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.last_failure_on = *now;
+        Ok(entry.count)
+    }
+
+    async fn lock(&self, key: &RateLimitKey, until: &DateTime<Utc>) -> StorageResult<()> {
+        if let Some(entry) = self.failures.lock().unwrap().get_mut(key) {
+            entry.locked_until = Some(*until);
+        }
+        Ok(())
+    }
+
+    async fn locked_until(
+        &self,
+        key: &RateLimitKey,
+        now: &DateTime<Utc>,
+    ) -> StorageResult<Option<DateTime<Utc>>> {
+        Ok(self
+            .failures
+            .lock()
+            .unwrap()
+            .get(key)
+            .and_then(|f| f.locked_until)
+            .filter(|until| until > now))
+    }
+}
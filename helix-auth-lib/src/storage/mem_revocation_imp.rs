@@ -0,0 +1,85 @@
+use crate::storage::error::*;
+use crate::storage::traits::RevocationStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct RevocationState {
+    consumed: HashMap<uuid::Uuid, DateTime<Utc>>,
+    revoked_families: HashSet<uuid::Uuid>,
+    revoked_users: HashMap<uuid::Uuid, DateTime<Utc>>,
+}
+
+//Single process store, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemRevocationStore {
+    state: Mutex<RevocationState>,
+}
+
+impl MemRevocationStore {
+    pub fn new() -> Self {
+        MemRevocationStore::default()
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MemRevocationStore {
+    async fn consume(
+        &self,
+        jti: &uuid::Uuid,
+        _family: &uuid::Uuid,
+        _user_uuid: &uuid::Uuid,
+        expires_on: &DateTime<Utc>,
+    ) -> StorageResult<bool> {
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now();
+        state.consumed.retain(|_, expires_on| *expires_on > now);
+
+        Ok(state.consumed.insert(*jti, *expires_on).is_none())
+    }
+
+    async fn is_consumed(&self, jti: &uuid::Uuid) -> StorageResult<bool> {
+        Ok(self.state.lock().unwrap().consumed.contains_key(jti))
+    }
+
+    async fn revoke_family(
+        &self,
+        family: &uuid::Uuid,
+        _user_uuid: &uuid::Uuid,
+    ) -> StorageResult<()> {
+        self.state.lock().unwrap().revoked_families.insert(*family);
+        Ok(())
+    }
+
+    async fn is_family_revoked(&self, family: &uuid::Uuid) -> StorageResult<bool> {
+        Ok(self.state.lock().unwrap().revoked_families.contains(family))
+    }
+
+    async fn revoke_user(
+        &self,
+        user_uuid: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .revoked_users
+            .insert(*user_uuid, *revoked_on);
+        Ok(())
+    }
+
+    async fn get_user_revoked_on(
+        &self,
+        user_uuid: &uuid::Uuid,
+    ) -> StorageResult<Option<DateTime<Utc>>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .revoked_users
+            .get(user_uuid)
+            .cloned())
+    }
+}
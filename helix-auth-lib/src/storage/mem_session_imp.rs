@@ -0,0 +1,93 @@
+use crate::session::Session;
+use crate::storage::error::*;
+use crate::storage::traits::SessionStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+//Single process store, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemSessionStore {
+    sessions: Mutex<HashMap<uuid::Uuid, Session>>,
+}
+
+impl MemSessionStore {
+    pub fn new() -> Self {
+        MemSessionStore::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemSessionStore {
+    async fn add_session(&self, session: &Session) -> StorageResult<()> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id, session.clone());
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &uuid::Uuid) -> StorageResult<Option<Session>> {
+        Ok(self.sessions.lock().unwrap().get(id).cloned())
+    }
+
+    async fn get_sessions_by_user(&self, user_uuid: &uuid::Uuid) -> StorageResult<Vec<Session>> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| s.user_uuid == *user_uuid && !s.is_revoked())
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen_on));
+        Ok(sessions)
+    }
+
+    async fn touch_session(
+        &self,
+        id: &uuid::Uuid,
+        last_seen_on: &DateTime<Utc>,
+        ip: Option<&str>,
+    ) -> StorageResult<()> {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.last_seen_on = *last_seen_on;
+            if let Some(ip) = ip {
+                session.ip = Some(ip.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        id: &uuid::Uuid,
+        user_uuid: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<bool> {
+        match self.sessions.lock().unwrap().get_mut(id) {
+            Some(session) if session.user_uuid == *user_uuid => {
+                session.revoked_on.get_or_insert(*revoked_on);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_other_sessions(
+        &self,
+        user_uuid: &uuid::Uuid,
+        keep: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<u64> {
+        let mut revoked = 0;
+        for session in self.sessions.lock().unwrap().values_mut() {
+            if session.user_uuid == *user_uuid && session.id != *keep && !session.is_revoked() {
+                session.revoked_on = Some(*revoked_on);
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+}
@@ -0,0 +1,117 @@
+use crate::api_key::ApiKey;
+use crate::storage::error::*;
+use crate::storage::traits::ApiKeyStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::{NoTls, Row};
+
+pub struct PgDbApiKeyStore {
+    pub pool: Pool,
+}
+
+impl PgDbApiKeyStore {
+    pub fn new(
+        database: String,
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+    ) -> PgDbApiKeyStore {
+        let mut cfg = Config::new();
+        cfg.dbname = Some(database);
+        cfg.host = Some(host);
+        cfg.port = Some(port);
+        cfg.user = Some(user);
+        cfg.password = Some(password);
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+
+        PgDbApiKeyStore {
+            pool: cfg.create_pool(NoTls).unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for PgDbApiKeyStore {
+    async fn add_api_key(&self, key: &ApiKey) -> StorageResult<()> {
+        let query = "
+        INSERT INTO auth.api_key (id, label, hash, user_name, user_, person_, scopes, created_on, expires_on, revoked_on)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);";
+
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                query,
+                &[
+                    &key.id,
+                    &key.label,
+                    &key.hash,
+                    &key.user,
+                    &key.user_uuid,
+                    &key.person_uuid,
+                    &key.scopes,
+                    &key.created_on,
+                    &key.expires_on,
+                    &key.revoked_on,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_api_key(&self, id: &uuid::Uuid) -> StorageResult<Option<ApiKey>> {
+        let query = "
+        SELECT id, label, hash, user_name, user_, person_, scopes, created_on, expires_on, revoked_on
+        FROM auth.api_key
+        WHERE id = $1;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&id]).await?;
+        Ok(rows.first().map(row_to_api_key))
+    }
+
+    async fn get_api_keys_by_user(&self, user_uuid: &uuid::Uuid) -> StorageResult<Vec<ApiKey>> {
+        let query = "
+        SELECT id, label, hash, user_name, user_, person_, scopes, created_on, expires_on, revoked_on
+        FROM auth.api_key
+        WHERE user_ = $1
+        ORDER BY created_on;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&user_uuid]).await?;
+        Ok(rows.iter().map(row_to_api_key).collect())
+    }
+
+    async fn revoke_api_key(
+        &self,
+        id: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<bool> {
+        let query = "
+        UPDATE auth.api_key
+        SET revoked_on = COALESCE(revoked_on, $2)
+        WHERE id = $1;";
+
+        let client = self.pool.get().await?;
+        let updated = client.execute(query, &[&id, &revoked_on]).await?;
+        Ok(updated > 0)
+    }
+}
+
+fn row_to_api_key(row: &Row) -> ApiKey {
+    ApiKey {
+        id: row.get("id"),
+        label: row.get("label"),
+        hash: row.get("hash"),
+        user: row.get("user_name"),
+        user_uuid: row.get("user_"),
+        person_uuid: row.get("person_"),
+        scopes: row.get("scopes"),
+        created_on: row.get("created_on"),
+        expires_on: row.get("expires_on"),
+        revoked_on: row.get("revoked_on"),
+    }
+}
@@ -0,0 +1,61 @@
+use crate::event::{AuthEvent, AuthEventSink};
+use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::NoTls;
+
+/// Appends events to `auth.auth_event`.
+///
+/// Inserts run in the background on the actix runtime, so `record` must be
+/// called from within it. A failed insert is reported through `tracing`.
+pub struct PgDbAuthEventSink {
+    pub pool: Pool,
+}
+
+impl PgDbAuthEventSink {
+    pub fn new(
+        database: String,
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+    ) -> PgDbAuthEventSink {
+        let mut cfg = Config::new();
+        cfg.dbname = Some(database);
+        cfg.host = Some(host);
+        cfg.port = Some(port);
+        cfg.user = Some(user);
+        cfg.password = Some(password);
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+
+        PgDbAuthEventSink {
+            pool: cfg.create_pool(NoTls).unwrap(),
+        }
+    }
+}
+
+impl AuthEventSink for PgDbAuthEventSink {
+    fn record(&self, event: &AuthEvent) {
+        let query = "
+        INSERT INTO auth.auth_event (event, user_, payload)
+        VALUES ($1, $2, $3::text::jsonb);";
+
+        let pool = self.pool.clone();
+        let name = event.name();
+        let user_uuid = event.user_uuid().cloned();
+        let payload = serde_json::to_string(event).unwrap_or_default();
+        actix_web::rt::spawn(async move {
+            let result = match pool.get().await {
+                Ok(client) => client
+                    .execute(query, &[&name, &user_uuid, &payload])
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                tracing::error!(target: "helix_auth", event = name, error = e.as_str(), "auth event lost");
+            }
+        });
+    }
+}
@@ -0,0 +1,160 @@
+use crate::rate_limit::TokenBucket;
+use crate::storage::error::*;
+use crate::storage::traits::{RateLimitKey, RateLimitStore};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use chrono::Duration;
+use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod};
+use rand::Rng;
+use tokio_postgres::NoTls;
+
+//Stale rows are pruned on one call in this many.
+const PRUNE_ONE_IN: u32 = 64;
+
+//Shared by every instance: each operation is a single atomic statement.
+pub struct PgDbRateLimitStore {
+    pub pool: Pool,
+}
+
+impl PgDbRateLimitStore {
+    pub fn new(
+        database: String,
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+    ) -> PgDbRateLimitStore {
+        let mut cfg = Config::new();
+        cfg.dbname = Some(database);
+        cfg.host = Some(host);
+        cfg.port = Some(port);
+        cfg.user = Some(user);
+        cfg.password = Some(password);
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+
+        PgDbRateLimitStore {
+            pool: cfg.create_pool(NoTls).unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PgDbRateLimitStore {
+    async fn take(
+        &self,
+        key: &RateLimitKey,
+        bucket: &TokenBucket,
+        now: &DateTime<Utc>,
+    ) -> StorageResult<Option<Duration>> {
+        //$2 burst, $3 refill per second. A new bucket starts full, like the mem
+        //store. SET expressions read the locked row as it was, `allowed` keeps
+        //the decision for RETURNING.
+        let query = "
+        INSERT INTO auth.rate_limit_bucket AS b (key_, tokens, allowed, updated_on)
+        VALUES (
+            $1,
+            CASE WHEN $2::float8 >= 1 THEN $2::float8 - 1 ELSE $2::float8 END,
+            $2::float8 >= 1,
+            $4
+        )
+        ON CONFLICT (key_) DO UPDATE SET
+            tokens = CASE
+                WHEN LEAST($2::float8, b.tokens + EXTRACT(EPOCH FROM ($4 - b.updated_on))::float8 * $3::float8) >= 1
+                THEN LEAST($2::float8, b.tokens + EXTRACT(EPOCH FROM ($4 - b.updated_on))::float8 * $3::float8) - 1
+                ELSE LEAST($2::float8, b.tokens + EXTRACT(EPOCH FROM ($4 - b.updated_on))::float8 * $3::float8)
+            END,
+            allowed = LEAST($2::float8, b.tokens + EXTRACT(EPOCH FROM ($4 - b.updated_on))::float8 * $3::float8) >= 1,
+            updated_on = $4
+        RETURNING tokens, allowed;";
+
+        let client = self.pool.get().await?;
+        //Buckets of the same kind refilled since their last request are full,
+        //the same as no row, like the mem store drops them.
+        if bucket.per_second > 0.0 && rand::thread_rng().gen_ratio(1, PRUNE_ONE_IN) {
+            let refill = Duration::milliseconds(
+                (f64::from(bucket.burst) / bucket.per_second * 1000.0).ceil() as i64,
+            );
+            let prune = "
+            DELETE FROM auth.rate_limit_bucket
+            WHERE split_part(key_, ':', 1) = split_part($1, ':', 1) AND updated_on < $2;";
+            client
+                .execute(prune, &[&key.to_key(), &(*now - refill)])
+                .await?;
+        }
+        let row = client
+            .query_one(
+                query,
+                &[
+                    &key.to_key(),
+                    &f64::from(bucket.burst),
+                    &bucket.per_second,
+                    now,
+                ],
+            )
+            .await?;
+        let tokens: f64 = row.get(0);
+        match row.get(1) {
+            true => Ok(None),
+            false => Ok(Some(bucket.wait(tokens))),
+        }
+    }
+
+    async fn record_failure(
+        &self,
+        key: &RateLimitKey,
+        now: &DateTime<Utc>,
+        forget_after: Duration,
+    ) -> StorageResult<u32> {
+        let query = "
+        INSERT INTO auth.rate_limit_failure AS f (key_, failures, last_failure_on)
+        VALUES ($1, 1, $2)
+        ON CONFLICT (key_) DO UPDATE SET
+            failures = CASE WHEN f.last_failure_on < $3 THEN 1 ELSE f.failures + 1 END,
+            last_failure_on = $2
+        RETURNING failures;";
+
+        let client = self.pool.get().await?;
+        //Forgotten failures, no longer locked out.
+        if rand::thread_rng().gen_ratio(1, PRUNE_ONE_IN) {
+            let prune = "
+            DELETE FROM auth.rate_limit_failure
+            WHERE last_failure_on < $1 AND (locked_until IS NULL OR locked_until < $2);";
+            client
+                .execute(prune, &[&(*now - forget_after), now])
+                .await?;
+        }
+        let row = client
+            .query_one(query, &[&key.to_key(), now, &(*now - forget_after)])
+            .await?;
+        let failures: i32 = row.get(0);
+        Ok(failures.max(0) as u32)
+    }
+
+    async fn lock(&self, key: &RateLimitKey, until: &DateTime<Utc>) -> StorageResult<()> {
+        let query = "
+        UPDATE auth.rate_limit_failure
+        SET locked_until = $2
+        WHERE key_ = $1;";
+
+        let client = self.pool.get().await?;
+        client.execute(query, &[&key.to_key(), until]).await?;
+        Ok(())
+    }
+
+    async fn locked_until(
+        &self,
+        key: &RateLimitKey,
+        now: &DateTime<Utc>,
+    ) -> StorageResult<Option<DateTime<Utc>>> {
+        let query = "
+        SELECT locked_until
+        FROM auth.rate_limit_failure
+        WHERE key_ = $1 AND locked_until > $2;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&key.to_key(), now]).await?;
+        Ok(rows.first().map(|row| row.get(0)))
+    }
+}
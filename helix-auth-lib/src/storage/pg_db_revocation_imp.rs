@@ -0,0 +1,119 @@
+use crate::storage::error::*;
+use crate::storage::traits::RevocationStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::NoTls;
+
+pub struct PgDbRevocationStore {
+    pub pool: Pool,
+}
+
+impl PgDbRevocationStore {
+    pub fn new(
+        database: String,
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+    ) -> PgDbRevocationStore {
+        let mut cfg = Config::new();
+        cfg.dbname = Some(database);
+        cfg.host = Some(host);
+        cfg.port = Some(port);
+        cfg.user = Some(user);
+        cfg.password = Some(password);
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+
+        PgDbRevocationStore {
+            pool: cfg.create_pool(NoTls).unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl RevocationStore for PgDbRevocationStore {
+    async fn consume(
+        &self,
+        jti: &uuid::Uuid,
+        family: &uuid::Uuid,
+        user_uuid: &uuid::Uuid,
+        expires_on: &DateTime<Utc>,
+    ) -> StorageResult<bool> {
+        let query = "
+        INSERT INTO auth.consumed_refresh_token (jti, family, user_, expires_on)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (jti) DO NOTHING
+        RETURNING jti;";
+
+        //Expired tokens are rejected before being consumed, their rows can go.
+        let prune = "DELETE FROM auth.consumed_refresh_token WHERE expires_on < now();";
+
+        let client = self.pool.get().await?;
+        client.execute(prune, &[]).await?;
+        let rows = client
+            .query(query, &[&jti, &family, &user_uuid, &expires_on])
+            .await?;
+
+        Ok(!rows.is_empty())
+    }
+
+    async fn is_consumed(&self, jti: &uuid::Uuid) -> StorageResult<bool> {
+        let query = "SELECT jti FROM auth.consumed_refresh_token WHERE jti = $1;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&jti]).await?;
+        Ok(!rows.is_empty())
+    }
+
+    async fn revoke_family(
+        &self,
+        family: &uuid::Uuid,
+        user_uuid: &uuid::Uuid,
+    ) -> StorageResult<()> {
+        let query = "
+        INSERT INTO auth.revoked_token_family (family, user_)
+        VALUES ($1, $2)
+        ON CONFLICT (family) DO NOTHING;";
+
+        let client = self.pool.get().await?;
+        client.execute(query, &[&family, &user_uuid]).await?;
+        Ok(())
+    }
+
+    async fn is_family_revoked(&self, family: &uuid::Uuid) -> StorageResult<bool> {
+        let query = "SELECT family FROM auth.revoked_token_family WHERE family = $1;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&family]).await?;
+        Ok(!rows.is_empty())
+    }
+
+    async fn revoke_user(
+        &self,
+        user_uuid: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<()> {
+        let query = "
+        INSERT INTO auth.revoked_user (user_, revoked_on)
+        VALUES ($1, $2)
+        ON CONFLICT (user_) DO UPDATE SET revoked_on = EXCLUDED.revoked_on;";
+
+        let client = self.pool.get().await?;
+        client.execute(query, &[&user_uuid, &revoked_on]).await?;
+        Ok(())
+    }
+
+    async fn get_user_revoked_on(
+        &self,
+        user_uuid: &uuid::Uuid,
+    ) -> StorageResult<Option<DateTime<Utc>>> {
+        let query = "SELECT revoked_on FROM auth.revoked_user WHERE user_ = $1;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&user_uuid]).await?;
+        Ok(rows.first().map(|row| row.get("revoked_on")))
+    }
+}
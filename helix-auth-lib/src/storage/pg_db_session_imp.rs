@@ -0,0 +1,149 @@
+use crate::session::Session;
+use crate::storage::error::*;
+use crate::storage::traits::SessionStore;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::{NoTls, Row};
+
+pub struct PgDbSessionStore {
+    pub pool: Pool,
+}
+
+impl PgDbSessionStore {
+    pub fn new(
+        database: String,
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+    ) -> PgDbSessionStore {
+        let mut cfg = Config::new();
+        cfg.dbname = Some(database);
+        cfg.host = Some(host);
+        cfg.port = Some(port);
+        cfg.user = Some(user);
+        cfg.password = Some(password);
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+
+        PgDbSessionStore {
+            pool: cfg.create_pool(NoTls).unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgDbSessionStore {
+    async fn add_session(&self, session: &Session) -> StorageResult<()> {
+        let query = "
+        INSERT INTO auth.session (id, user_, device, ip, user_agent, created_on, last_seen_on, revoked_on)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8);";
+
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                query,
+                &[
+                    &session.id,
+                    &session.user_uuid,
+                    &session.device,
+                    &session.ip,
+                    &session.user_agent,
+                    &session.created_on,
+                    &session.last_seen_on,
+                    &session.revoked_on,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &uuid::Uuid) -> StorageResult<Option<Session>> {
+        let query = "
+        SELECT id, user_, device, ip, user_agent, created_on, last_seen_on, revoked_on
+        FROM auth.session
+        WHERE id = $1;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&id]).await?;
+        Ok(rows.first().map(row_to_session))
+    }
+
+    async fn get_sessions_by_user(&self, user_uuid: &uuid::Uuid) -> StorageResult<Vec<Session>> {
+        let query = "
+        SELECT id, user_, device, ip, user_agent, created_on, last_seen_on, revoked_on
+        FROM auth.session
+        WHERE user_ = $1 AND revoked_on IS NULL
+        ORDER BY last_seen_on DESC;";
+
+        let client = self.pool.get().await?;
+        let rows = client.query(query, &[&user_uuid]).await?;
+        Ok(rows.iter().map(row_to_session).collect())
+    }
+
+    async fn touch_session(
+        &self,
+        id: &uuid::Uuid,
+        last_seen_on: &DateTime<Utc>,
+        ip: Option<&str>,
+    ) -> StorageResult<()> {
+        let query = "
+        UPDATE auth.session
+        SET last_seen_on = $2, ip = COALESCE($3, ip)
+        WHERE id = $1;";
+
+        let client = self.pool.get().await?;
+        client.execute(query, &[&id, &last_seen_on, &ip]).await?;
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        id: &uuid::Uuid,
+        user_uuid: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<bool> {
+        let query = "
+        UPDATE auth.session
+        SET revoked_on = COALESCE(revoked_on, $3)
+        WHERE id = $1 AND user_ = $2;";
+
+        let client = self.pool.get().await?;
+        let updated = client
+            .execute(query, &[&id, &user_uuid, &revoked_on])
+            .await?;
+        Ok(updated > 0)
+    }
+
+    async fn revoke_other_sessions(
+        &self,
+        user_uuid: &uuid::Uuid,
+        keep: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<u64> {
+        let query = "
+        UPDATE auth.session
+        SET revoked_on = $3
+        WHERE user_ = $1 AND id <> $2 AND revoked_on IS NULL;";
+
+        let client = self.pool.get().await?;
+        Ok(client
+            .execute(query, &[&user_uuid, &keep, &revoked_on])
+            .await?)
+    }
+}
+
+fn row_to_session(row: &Row) -> Session {
+    Session {
+        id: row.get("id"),
+        user_uuid: row.get("user_"),
+        device: row.get("device"),
+        ip: row.get("ip"),
+        user_agent: row.get("user_agent"),
+        created_on: row.get("created_on"),
+        last_seen_on: row.get("last_seen_on"),
+        revoked_on: row.get("revoked_on"),
+    }
+}
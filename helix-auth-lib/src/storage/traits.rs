@@ -0,0 +1,166 @@
+use crate::api_key::ApiKey;
+use crate::oauth::{AuthorizationCode, OAuthClient};
+use crate::rate_limit::TokenBucket;
+use crate::session::Session;
+use crate::storage::error::*;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use chrono::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DenylistKey {
+    //A single access token, by `jti`.
+    Token(uuid::Uuid),
+    //Every access token of a user.
+    User(uuid::Uuid),
+}
+
+#[async_trait]
+pub trait Denylist: Send + Sync {
+    //Denies the key until `until`, usually the expiry of the longest lived access token.
+    async fn deny(&self, key: &DenylistKey, until: &DateTime<Utc>) -> StorageResult<()>;
+
+    async fn is_denied(&self, key: &DenylistKey) -> StorageResult<bool>;
+}
+
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    //Marks a refresh token as used. Returns false when it had already been consumed.
+    async fn consume(
+        &self,
+        jti: &uuid::Uuid,
+        family: &uuid::Uuid,
+        user_uuid: &uuid::Uuid,
+        expires_on: &DateTime<Utc>,
+    ) -> StorageResult<bool>;
+
+    async fn is_consumed(&self, jti: &uuid::Uuid) -> StorageResult<bool>;
+
+    async fn revoke_family(&self, family: &uuid::Uuid, user_uuid: &uuid::Uuid)
+        -> StorageResult<()>;
+
+    async fn is_family_revoked(&self, family: &uuid::Uuid) -> StorageResult<bool>;
+
+    //Every token of the user issued at or before `revoked_on` is revoked.
+    async fn revoke_user(
+        &self,
+        user_uuid: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<()>;
+
+    async fn get_user_revoked_on(
+        &self,
+        user_uuid: &uuid::Uuid,
+    ) -> StorageResult<Option<DateTime<Utc>>>;
+}
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn add_api_key(&self, key: &ApiKey) -> StorageResult<()>;
+
+    async fn get_api_key(&self, id: &uuid::Uuid) -> StorageResult<Option<ApiKey>>;
+
+    async fn get_api_keys_by_user(&self, user_uuid: &uuid::Uuid) -> StorageResult<Vec<ApiKey>>;
+
+    //Returns false when the key does not exist.
+    async fn revoke_api_key(
+        &self,
+        id: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<bool>;
+}
+
+#[async_trait]
+pub trait OAuthClientStore: Send + Sync {
+    async fn add_client(&self, client: &OAuthClient) -> StorageResult<()>;
+
+    async fn get_client(&self, client_id: &str) -> StorageResult<Option<OAuthClient>>;
+}
+
+#[async_trait]
+pub trait AuthorizationCodeStore: Send + Sync {
+    async fn add_code(&self, code: &AuthorizationCode) -> StorageResult<()>;
+
+    //Removes and returns the code: codes are single use.
+    async fn take_code(&self, code: &str) -> StorageResult<Option<AuthorizationCode>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    Ip(String),
+    User(uuid::Uuid),
+    Service(String),
+}
+
+impl RateLimitKey {
+    //Single column form, e.g. `ip:10.0.0.1`.
+    pub fn to_key(&self) -> String {
+        match self {
+            RateLimitKey::Ip(ip) => format!("ip:{}", ip),
+            RateLimitKey::User(user_uuid) => format!("user:{}", user_uuid),
+            RateLimitKey::Service(service) => format!("service:{}", service),
+        }
+    }
+}
+
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    //Takes a token from the bucket of `key`. Returns the wait before the next
+    //token when the bucket is empty.
+    async fn take(
+        &self,
+        key: &RateLimitKey,
+        bucket: &TokenBucket,
+        now: &DateTime<Utc>,
+    ) -> StorageResult<Option<Duration>>;
+
+    //Counts a failure and returns the count, restarting from one when the
+    //previous failure is older than `forget_after`.
+    async fn record_failure(
+        &self,
+        key: &RateLimitKey,
+        now: &DateTime<Utc>,
+        forget_after: Duration,
+    ) -> StorageResult<u32>;
+
+    async fn lock(&self, key: &RateLimitKey, until: &DateTime<Utc>) -> StorageResult<()>;
+
+    async fn locked_until(
+        &self,
+        key: &RateLimitKey,
+        now: &DateTime<Utc>,
+    ) -> StorageResult<Option<DateTime<Utc>>>;
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn add_session(&self, session: &Session) -> StorageResult<()>;
+
+    async fn get_session(&self, id: &uuid::Uuid) -> StorageResult<Option<Session>>;
+
+    //Sessions not revoked yet, most recently seen first.
+    async fn get_sessions_by_user(&self, user_uuid: &uuid::Uuid) -> StorageResult<Vec<Session>>;
+
+    async fn touch_session(
+        &self,
+        id: &uuid::Uuid,
+        last_seen_on: &DateTime<Utc>,
+        ip: Option<&str>,
+    ) -> StorageResult<()>;
+
+    //Returns false when the user has no such session.
+    async fn revoke_session(
+        &self,
+        id: &uuid::Uuid,
+        user_uuid: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<bool>;
+
+    //Revokes every session of the user but `keep`, returns how many.
+    async fn revoke_other_sessions(
+        &self,
+        user_uuid: &uuid::Uuid,
+        keep: &uuid::Uuid,
+        revoked_on: &DateTime<Utc>,
+    ) -> StorageResult<u64>;
+}
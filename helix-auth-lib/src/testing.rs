@@ -0,0 +1,260 @@
+//Test support, behind the `testing` feature: tokens minted without any
+//environment variable, signed with a fixed HMAC secret. Never outside tests.
+
+use crate::claims::{self, Claims, Grants};
+use crate::config::AuthConfig;
+use crate::keys::AuthKey;
+use crate::keyset::KeySet;
+use crate::middleware::AuthValidator;
+use crate::tokenizer::Tokenizer;
+use crate::HelixAuth;
+use actix_http::Request;
+use actix_service::Service;
+use actix_web::dev::ServiceResponse;
+use actix_web::test::{self, TestRequest};
+use chrono::prelude::*;
+use chrono::Duration;
+use std::sync::Arc;
+
+pub const TEST_ISSUER: &str = "helix-test";
+pub const TEST_SECRET: &[u8] = b"helix-test-secret";
+pub const TEST_USER: &str = "test-user";
+pub const TEST_USER_UUID: uuid::Uuid = uuid::Uuid::from_bytes([
+    0x7e, 0x57, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+]);
+
+/// Clock stopped at one instant, tokens minted from it are reproducible.
+///
+/// Validation still uses the system time: freeze the clock in the past to
+/// get tokens that are already expired.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrozenClock {
+    now: DateTime<Utc>,
+}
+
+impl FrozenClock {
+    //Frozen at the current second.
+    pub fn new() -> Self {
+        FrozenClock::at(Utc.timestamp(Utc::now().timestamp(), 0))
+    }
+
+    pub fn at(now: DateTime<Utc>) -> Self {
+        FrozenClock { now }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now
+    }
+
+    pub fn advance(&mut self, duration: Duration) {
+        self.now = self.now + duration;
+    }
+}
+
+impl Default for FrozenClock {
+    fn default() -> Self {
+        FrozenClock::new()
+    }
+}
+
+/// Signs test tokens with `TEST_SECRET` for the `TEST_ISSUER`.
+pub struct TestSigner {
+    config: AuthConfig,
+    clock: FrozenClock,
+}
+
+impl TestSigner {
+    pub fn new() -> Self {
+        let config = AuthConfig::builder(TEST_ISSUER, KeySet::from(AuthKey::hmac(TEST_SECRET)))
+            .build()
+            .expect("the test configuration is valid");
+        TestSigner::with_config(config)
+    }
+
+    //Signs with the keys of `config`, e.g. one with an audience or a leeway.
+    pub fn with_config(config: AuthConfig) -> Self {
+        TestSigner {
+            config,
+            clock: FrozenClock::new(),
+        }
+    }
+
+    pub fn clock(mut self, clock: FrozenClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
+    }
+
+    //Validates what this signer mints.
+    pub fn auth(&self) -> Arc<HelixAuth> {
+        Arc::new(HelixAuth::new(self.config.clone()))
+    }
+
+    //Protects `/api` with `auth()`.
+    pub fn validator(&self) -> AuthValidator {
+        AuthValidator::with_auth(self.auth(), vec![])
+    }
+
+    /// Valid access token of `TEST_USER`, to customize before `build`.
+    pub fn token(&self) -> TestToken<'_> {
+        let family = uuid::Uuid::new_v5(&TEST_USER_UUID, b"family");
+        let claims = claims::get_access_token_claims(
+            &self.config,
+            TEST_USER,
+            &TEST_USER_UUID,
+            &TEST_USER_UUID,
+            &family,
+        );
+        TestToken {
+            signer: self,
+            claims,
+            issued_on: self.clock.now(),
+            lifetime: self.config.access_token_lifetime(),
+        }
+    }
+
+    pub fn sign(&self, claims: &Claims) -> String {
+        Tokenizer::new(self.config.keys())
+            .claims(claims.clone())
+            .generate()
+            .expect("test claims are serializable")
+    }
+}
+
+impl Default for TestSigner {
+    fn default() -> Self {
+        TestSigner::new()
+    }
+}
+
+/// Claims of a test token. Same inputs and clock, same token.
+pub struct TestToken<'a> {
+    signer: &'a TestSigner,
+    claims: Claims,
+    issued_on: DateTime<Utc>,
+    lifetime: Duration,
+}
+
+impl<'a> TestToken<'a> {
+    pub fn user(mut self, user: &str, user_uuid: &uuid::Uuid) -> Self {
+        self.claims.user = user.to_owned();
+        self.claims.user_uuid = *user_uuid;
+        self.claims.person_uuid = *user_uuid;
+        self
+    }
+
+    pub fn grants(mut self, grants: &Grants) -> Self {
+        self.claims = self.claims.with_grants(grants);
+        self
+    }
+
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.claims.aud = Some(audience.to_owned());
+        self
+    }
+
+    pub fn issued_on(mut self, issued_on: DateTime<Utc>) -> Self {
+        self.issued_on = issued_on;
+        self
+    }
+
+    //Expired a minute past the leeway, whatever the clock.
+    pub fn expired(self) -> Self {
+        let expired_on = Utc::now() - Duration::seconds(self.signer.config.leeway() as i64 + 60);
+        let lifetime = self.lifetime;
+        self.issued_on(expired_on - lifetime)
+    }
+
+    pub fn wrong_issuer(mut self) -> Self {
+        self.claims.iss = format!("not-{}", TEST_ISSUER);
+        self
+    }
+
+    //A refresh token where an access token is expected.
+    pub fn wrong_subject(mut self) -> Self {
+        self.claims.sub = claims::REFRESH_TOKEN_SUBJECT.to_owned();
+        self
+    }
+
+    pub fn claims(&self) -> Claims {
+        let mut claims = self.claims.clone();
+        claims.iat = self.issued_on.timestamp();
+        claims.iat_ms = claims.iat_ms.map(|_| self.issued_on.timestamp_millis());
+        claims.exp = (self.issued_on + self.lifetime).timestamp();
+        claims.nbf = claims.nbf.map(|_| claims.iat);
+        //Derived from the rest of the claims, for reproducible tokens.
+        let seed = format!("{}:{}:{}", claims.sub, claims.user, claims.iat);
+        claims.jti = Some(uuid::Uuid::new_v5(&TEST_USER_UUID, seed.as_bytes()));
+        claims
+    }
+
+    pub fn build(&self) -> String {
+        self.signer.sign(&self.claims())
+    }
+}
+
+/// Adds `Authorization: Bearer <token>` to a test request.
+pub fn authenticated(request: TestRequest, token: &str) -> TestRequest {
+    request.header("Authorization", format!("Bearer {}", token))
+}
+
+/// Calls an initialized test service with a bearer token.
+pub async fn call_authenticated<S, B, E>(
+    app: &mut S,
+    request: TestRequest,
+    token: &str,
+) -> ServiceResponse<B>
+where
+    S: Service<Request = Request, Response = ServiceResponse<B>, Error = E>,
+    E: std::fmt::Debug,
+{
+    test::call_service(app, authenticated(request, token).to_request()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::AuthenticatedUser;
+    use actix_web::http::StatusCode;
+    use actix_web::{web, App, HttpResponse};
+
+    async fn me(user: AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.get_user().to_owned())
+    }
+
+    #[actix_rt::test]
+    async fn minted_tokens_are_deterministic_and_checked() {
+        let signer = TestSigner::new();
+        assert_eq!(signer.token().build(), signer.token().build());
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(signer.validator())
+                .route("/api/me", web::get().to(me)),
+        )
+        .await;
+        let mut statuses = Vec::new();
+        for token in &[
+            signer.token().user("alice", &uuid::Uuid::new_v4()).build(),
+            signer.token().expired().build(),
+            signer.token().wrong_issuer().build(),
+            signer.token().wrong_subject().build(),
+        ] {
+            let response =
+                call_authenticated(&mut app, TestRequest::get().uri("/api/me"), token).await;
+            statuses.push(response.status());
+        }
+        assert_eq!(
+            vec![
+                StatusCode::OK,
+                StatusCode::UNAUTHORIZED,
+                StatusCode::UNAUTHORIZED,
+                StatusCode::UNAUTHORIZED
+            ],
+            statuses
+        );
+    }
+}
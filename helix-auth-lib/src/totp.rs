@@ -0,0 +1,350 @@
+//RFC 6238 time based one-time passwords (HMAC-SHA1), as expected by authenticator apps.
+use crate::error::*;
+use crate::password::{constant_time_eq, Passwords};
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha1::Sha1;
+use std::fmt;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Shared secret and parameters of a TOTP second factor.
+#[derive(Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    digits: u32,
+    period: u64,
+    skew: u64,
+}
+
+//The secret never ends up in logs.
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("secret", &"<redacted>")
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("skew", &self.skew)
+            .finish()
+    }
+}
+
+/// What a client needs to enroll the secret in an authenticator app.
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpProvisioning {
+    pub secret: String,
+    pub uri: String,
+    pub digits: u32,
+    pub period: u64,
+}
+
+impl Totp {
+    //160 bits secret, as recommended by RFC 4226.
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; 20];
+        OsRng.fill_bytes(&mut secret);
+        Totp::new(secret)
+    }
+
+    pub fn new(secret: Vec<u8>) -> Self {
+        Totp {
+            secret,
+            digits: 6,
+            period: 30,
+            skew: 1,
+        }
+    }
+
+    pub fn from_base32(secret: &str) -> HelixAuthResult<Self> {
+        base32_decode(secret)
+            .map(Totp::new)
+            .ok_or_else(|| HelixAuthError::InvalidKey("malformed base32 TOTP secret".to_owned()))
+    }
+
+    //6 to 8 digits, what authenticator apps support.
+    pub fn digits(mut self, digits: u32) -> HelixAuthResult<Self> {
+        if !(6..=8).contains(&digits) {
+            return Err(HelixAuthError::InvalidConfiguration(format!(
+                "TOTP codes have 6 to 8 digits, not {}",
+                digits
+            )));
+        }
+        self.digits = digits;
+        Ok(self)
+    }
+
+    pub fn period(mut self, seconds: u64) -> HelixAuthResult<Self> {
+        if seconds == 0 {
+            return Err(HelixAuthError::InvalidConfiguration(
+                "TOTP period must be at least one second".to_owned(),
+            ));
+        }
+        self.period = seconds;
+        Ok(self)
+    }
+
+    //Steps accepted before and after the current one, for clock drift.
+    pub fn skew(mut self, steps: u64) -> Self {
+        self.skew = steps;
+        self
+    }
+
+    pub fn secret_base32(&self) -> String {
+        base32_encode(&self.secret)
+    }
+
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            uri_encode(issuer),
+            uri_encode(account),
+            self.secret_base32(),
+            uri_encode(issuer),
+            self.digits,
+            self.period
+        )
+    }
+
+    pub fn provisioning(&self, issuer: &str, account: &str) -> TotpProvisioning {
+        TotpProvisioning {
+            secret: self.secret_base32(),
+            uri: self.provisioning_uri(issuer, account),
+            digits: self.digits,
+            period: self.period,
+        }
+    }
+
+    pub fn code_at(&self, time: &DateTime<Utc>) -> String {
+        self.code_for_step(time.timestamp().max(0) as u64 / self.period)
+    }
+
+    /// Checks a code against the steps around `time`.
+    ///
+    /// Returns the matching time step: store it and refuse codes of the same
+    /// or an older step to make codes single use.
+    pub fn verify(&self, code: &str, time: &DateTime<Utc>) -> Option<u64> {
+        self.verify_after(code, time, None)
+    }
+
+    //Like `verify`, refusing the steps up to `last_step`, already used.
+    pub fn verify_after(
+        &self,
+        code: &str,
+        time: &DateTime<Utc>,
+        last_step: Option<u64>,
+    ) -> Option<u64> {
+        let code = code.trim();
+        let current = time.timestamp().max(0) as u64 / self.period;
+        let first = match last_step {
+            Some(step) => step.saturating_add(1),
+            None => 0,
+        };
+
+        (current.saturating_sub(self.skew).max(first)..=current.saturating_add(self.skew))
+            .find(|step| constant_time_eq(self.code_for_step(*step).as_bytes(), code.as_bytes()))
+    }
+
+    fn code_for_step(&self, step: u64) -> String {
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC accepts any key size");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        //Dynamic truncation
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary as u64 % 10u64.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+}
+
+/// Single use recovery codes, stored as Argon2id hashes.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryCodes {
+    hashes: Vec<String>,
+}
+
+impl RecoveryCodes {
+    //Returns the codes to show once to the user, and their hashes to store.
+    pub fn generate(count: usize) -> HelixAuthResult<(Vec<String>, RecoveryCodes)> {
+        RecoveryCodes::generate_with(count, &Passwords::default())
+    }
+
+    //80 bits codes, hashed with the given Argon2id parameters.
+    pub fn generate_with(
+        count: usize,
+        passwords: &Passwords,
+    ) -> HelixAuthResult<(Vec<String>, RecoveryCodes)> {
+        let codes: Vec<String> = (0..count)
+            .map(|_| {
+                let mut bytes = [0u8; 10];
+                OsRng.fill_bytes(&mut bytes);
+                let code = base32_encode(&bytes).to_lowercase();
+                format!(
+                    "{}-{}-{}-{}",
+                    &code[..4],
+                    &code[4..8],
+                    &code[8..12],
+                    &code[12..]
+                )
+            })
+            .collect();
+        let hashes = codes
+            .iter()
+            .map(|c| passwords.hash(&normalize_recovery_code(c)))
+            .collect::<HelixAuthResult<_>>()?;
+        Ok((codes, RecoveryCodes { hashes }))
+    }
+
+    pub fn from_hashes(hashes: Vec<String>) -> Self {
+        RecoveryCodes { hashes }
+    }
+
+    pub fn hashes(&self) -> &[String] {
+        &self.hashes
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.hashes.len()
+    }
+
+    //Removes the code when it matches: persist `hashes` afterwards.
+    pub fn consume(&mut self, code: &str) -> HelixAuthResult<bool> {
+        let code = normalize_recovery_code(code);
+        let passwords = Passwords::default();
+        for index in 0..self.hashes.len() {
+            if passwords.verify(&code, &self.hashes[index])? {
+                self.hashes.remove(index);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+//Codes are typed by hand: case, spaces and dashes don't matter.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn base32_encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity((input.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in input {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+//Lenient: ignores padding, spaces and case.
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in input.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    match out.is_empty() {
+        true => None,
+        false => Some(out),
+    }
+}
+
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::Argon2Params;
+
+    #[test]
+    fn rfc6238_vectors_and_drift_window() {
+        let totp = Totp::new(b"12345678901234567890".to_vec())
+            .digits(8)
+            .unwrap();
+        let at = |t| Utc.timestamp_opt(t, 0).unwrap();
+
+        assert_eq!("94287082", totp.code_at(&at(59)));
+        assert_eq!("07081804", totp.code_at(&at(1_111_111_109)));
+        assert_eq!("89005924", totp.code_at(&at(1_234_567_890)));
+
+        let code = totp.code_at(&at(1_234_567_890));
+        assert!(totp.verify(&code, &at(1_234_567_890 + 30)).is_some());
+        assert!(totp.verify(&code, &at(1_234_567_890 + 90)).is_none());
+
+        let restored = Totp::from_base32(&totp.secret_base32())
+            .unwrap()
+            .digits(8)
+            .unwrap();
+        assert_eq!(code, restored.code_at(&at(1_234_567_890)));
+        assert_eq!("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", totp.secret_base32());
+
+        assert!(Totp::generate().digits(20).is_err());
+        assert!(Totp::generate().period(0).is_err());
+    }
+
+    #[test]
+    fn recovery_codes_are_single_use() {
+        let passwords = Passwords::new(Argon2Params {
+            memory_kib: 256,
+            iterations: 1,
+            parallelism: 1,
+        });
+        let (codes, mut stored) = RecoveryCodes::generate_with(8, &passwords).unwrap();
+
+        assert_eq!(19, codes[0].len());
+        assert!(stored.hashes()[0].starts_with("$argon2id$"));
+        assert_ne!(stored.hashes()[0], stored.hashes()[1]);
+        assert_eq!(8, stored.remaining());
+        assert!(stored.consume(&codes[3].to_uppercase()).unwrap());
+        assert!(!stored.consume(&codes[3]).unwrap());
+        assert_eq!(7, stored.remaining());
+    }
+
+    #[test]
+    fn debug_output_hides_the_secret() {
+        let totp = Totp::new(b"12345678901234567890".to_vec());
+
+        let debug = format!("{:?}", totp);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("49, 50"));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::claims::Claims;
+use crate::cookie::CookieConfig;
+use crate::error::*;
+use crate::event::AuthEvent;
+use crate::rate_limit::RateLimiter;
+use crate::routes::RouteRules;
+use crate::storage::traits::{Denylist, DenylistKey};
+use crate::HelixAuth;
+use actix_web::cookie::Cookie;
+use actix_web::http::Method;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// What the validator reads from a request, whatever the web framework.
+///
+/// Each adapter (actix middleware, tower layer) builds one per request.
+#[derive(Debug, Clone)]
+pub struct AuthRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub peer: Option<IpAddr>,
+}
+
+impl AuthRequest {
+    pub fn new(method: Method, path: &str) -> Self {
+        AuthRequest {
+            method,
+            path: path.to_owned(),
+            headers: Vec::new(),
+            peer: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn peer(mut self, peer: Option<IpAddr>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    //First value of a header, names are case insensitive.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    //Every value of a header, in order.
+    pub fn get_headers(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|pair| Cookie::parse_encoded(pair.trim()).ok())
+            .find(|cookie| cookie.name() == name)
+            .map(|cookie| cookie.value().to_owned())
+    }
+}
+
+/// Framework neutral core of the request validation.
+///
+/// Decides from an `AuthRequest` whether the route is public, validates the
+/// token (bearer, API key or cookie), then applies the denylist and rate
+/// limits. `AuthValidator` and, with the `tower` feature, `AuthLayer` are
+/// adapters over it.
+#[derive(Clone)]
+pub struct RequestValidator {
+    rules: Arc<RouteRules>,
+    auth: Arc<HelixAuth>,
+    denylist: Option<Arc<dyn Denylist>>,
+    cookies: Option<Arc<CookieConfig>>,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl RequestValidator {
+    pub fn builder(auth: Arc<HelixAuth>) -> RequestValidatorBuilder {
+        RequestValidatorBuilder {
+            auth,
+            rules: RouteRules::default(),
+            denylist: None,
+            cookies: None,
+            limiter: None,
+        }
+    }
+
+    //Protects `/api`, except the exact `exception_uri` paths.
+    pub fn with_exceptions(auth: Arc<HelixAuth>, exception_uri: &[String]) -> Self {
+        RequestValidator {
+            rules: Arc::new(RouteRules::with_exceptions(exception_uri)),
+            auth,
+            denylist: None,
+            cookies: None,
+            limiter: None,
+        }
+    }
+
+    pub(crate) fn set_denylist(&mut self, denylist: Arc<dyn Denylist>) {
+        self.denylist = Some(denylist);
+    }
+
+    pub(crate) fn set_cookies(&mut self, cookies: CookieConfig) {
+        self.cookies = Some(Arc::new(cookies));
+    }
+
+    pub(crate) fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        self.limiter = Some(Arc::new(limiter));
+    }
+
+    /// Claims of the request, `None` on public routes.
+    ///
+    /// Errors are the rejections to answer, already reported to the event sink.
+    pub async fn validate(&self, req: &AuthRequest) -> HelixAuthResult<Option<Claims>> {
+        let result = match self.rules.is_protected(&req.method, &req.path) {
+            true => self.authenticate(req).await.map(Some),
+            false => self.check_anonymous(req).await.map(|_| None),
+        };
+
+        if let Err(error) = &result {
+            self.auth.emit(AuthEvent::RequestRejected {
+                method: req.method.to_string(),
+                path: req.path.clone(),
+                peer: req.peer.map(|ip| ip.to_string()),
+                reason: error.code().to_owned(),
+            });
+        }
+        result
+    }
+
+    async fn check_anonymous(&self, req: &AuthRequest) -> HelixAuthResult<()> {
+        match &self.limiter {
+            Some(limiter) => limiter.check_anonymous(client_ip(limiter, req)).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn authenticate(&self, req: &AuthRequest) -> HelixAuthResult<Claims> {
+        //Locked out IPs are not even validated.
+        if let Some(limiter) = &self.limiter {
+            limiter.check_lockout(client_ip(limiter, req)).await?;
+        }
+
+        let claims = match self.claims(req).await {
+            Ok(claims) => claims,
+            Err(e) => {
+                match e {
+                    HelixAuthError::MissingToken | HelixAuthError::Storage { .. } => {}
+                    _ => {
+                        self.auth.emit(AuthEvent::validation_failed(&e));
+                        if let Some(limiter) = &self.limiter {
+                            limiter.failed(client_ip(limiter, req)).await?;
+                        }
+                    }
+                }
+                return Err(e);
+            }
+        };
+
+        if let Some(denylist) = &self.denylist {
+            let mut keys = vec![DenylistKey::User(*claims.get_user_uuid())];
+            if let Some(jti) = claims.get_jti() {
+                keys.push(DenylistKey::Token(*jti));
+            }
+
+            for key in &keys {
+                //Denylist unreachable: fail closed with a 503
+                if denylist.is_denied(key).await? {
+                    return Err(HelixAuthError::RevokedToken);
+                }
+            }
+        }
+
+        if let Some(limiter) = &self.limiter {
+            limiter.check_authenticated(&claims).await?;
+        }
+        Ok(claims)
+    }
+
+    //Valid Authorization header, API key or cookie
+    async fn claims(&self, req: &AuthRequest) -> HelixAuthResult<Claims> {
+        if let Some(value) = req.get_header("Authorization") {
+            return match api_key(value) {
+                Some(key) => self.auth.api_key_claims(key).await,
+                None => self.auth.token_data(value),
+            };
+        }
+
+        match &self.cookies {
+            Some(cookies) => match cookies.request_access_token(req) {
+                Some(token) => {
+                    cookies.check_request_csrf(req)?;
+                    self.auth.access_claims(&token)
+                }
+                None => Err(HelixAuthError::MissingToken),
+            },
+            None => Err(HelixAuthError::MissingToken),
+        }
+    }
+}
+
+/// Route rules of a `RequestValidator`, see `RoutePattern` for the syntax.
+///
+/// Without any `protect` call, `/api` is protected.
+pub struct RequestValidatorBuilder {
+    auth: Arc<HelixAuth>,
+    rules: RouteRules,
+    denylist: Option<Arc<dyn Denylist>>,
+    cookies: Option<Arc<CookieConfig>>,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl RequestValidatorBuilder {
+    //Paths starting with `prefix` require a token, like the historical `/api`
+    //rule which covers `/apidoc` too.
+    pub fn protect(mut self, prefix: &str) -> Self {
+        self.rules.protect(prefix);
+        self
+    }
+
+    //Paths starting with the segments of `prefix` require a token.
+    pub fn protect_segments(mut self, prefix: &str) -> Self {
+        self.rules.protect_segments(prefix);
+        self
+    }
+
+    //Public for every method.
+    pub fn public(mut self, pattern: &str) -> Self {
+        self.rules.public(None, pattern);
+        self
+    }
+
+    //Public for one method only, e.g. public GET with protected POST.
+    pub fn public_method(mut self, method: Method, pattern: &str) -> Self {
+        self.rules.public(Some(method), pattern);
+        self
+    }
+
+    //Rejects valid tokens whose `jti` or user is denied. Wrap remote
+    //denylists in a `CachedDenylist`, it is consulted on every request.
+    pub fn denylist(mut self, denylist: Arc<dyn Denylist>) -> Self {
+        self.denylist = Some(denylist);
+        self
+    }
+
+    //The `Authorization` header keeps precedence over the cookie.
+    pub fn cookies(mut self, cookies: CookieConfig) -> Self {
+        self.cookies = Some(Arc::new(cookies));
+        self
+    }
+
+    //Per IP on public routes, per user on protected ones, with lockouts after
+    //failed validations. Behind a reverse proxy, list it in the limiter's
+    //`trusted_proxies`.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(Arc::new(limiter));
+        self
+    }
+
+    pub fn build(self) -> RequestValidator {
+        let mut rules = self.rules;
+        if !rules.has_protected() {
+            rules.protect("/api");
+        }
+
+        RequestValidator {
+            rules: Arc::new(rules),
+            auth: self.auth,
+            denylist: self.denylist,
+            cookies: self.cookies,
+            limiter: self.limiter,
+        }
+    }
+}
+
+fn client_ip(limiter: &RateLimiter, req: &AuthRequest) -> Option<IpAddr> {
+    limiter.client_ip(
+        req.peer,
+        &req.get_headers("Forwarded"),
+        &req.get_headers("X-Forwarded-For"),
+    )
+}
+
+//Key of an `Authorization: ApiKey <key>` header.
+fn api_key(value: &str) -> Option<&str> {
+    match value.split_once(' ') {
+        Some((scheme, key)) if scheme.eq_ignore_ascii_case("apikey") => Some(key.trim()),
+        _ => None,
+    }
+}
@@ -5,6 +5,12 @@ pub mod version;
 
 pub struct Configuration;
 
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Configuration {
     pub fn new() -> Self {
         //Load configuration into env variables.
@@ -32,7 +38,7 @@ impl Configuration {
 
         //Return string of {IP}:{PORT}
         addr.push_str(&ip);
-        addr.push_str(&":".to_owned());
+        addr.push(':');
         addr.push_str(&port);
         addr
     }
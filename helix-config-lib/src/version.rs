@@ -21,8 +21,8 @@ impl Version {
         git_commit_date: String,
     ) -> Version {
         Version {
-            version: version,
-            version_name: version_name,
+            version,
+            version_name,
             git_version: GitVersion {
                 commit_message: git_commit_message,
                 commit_short_hash: git_commit_short_hash,
@@ -14,6 +14,7 @@ pub struct Item<T> {
 }
 
 impl<T> Item<T> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: uuid::Uuid,
         configuration: Option<T>,
@@ -25,14 +26,14 @@ impl<T> Item<T> {
         type_id: String,
     ) -> Item<T> {
         Item {
-            id: id,
-            configuration: configuration,
-            expired_after: expired_after,
-            refresh_every: refresh_every,
-            created_on: created_on,
-            updated_on: updated_on,
-            owner: owner,
-            type_id: type_id,
+            id,
+            configuration,
+            expired_after,
+            refresh_every,
+            created_on,
+            updated_on,
+            owner,
+            type_id,
         }
     }
 }
@@ -60,10 +60,7 @@ impl<T: DeserializeOwned + std::marker::Send + std::marker::Sync> ItemStorageTra
 
         let client = self.pool.get().await.unwrap();
         for row in client.query(query, &[&type_id, &owner_uuid]).await? {
-            let parsed_config: Option<T> = match serde_json::from_value(row.get("configuration")) {
-                Ok(config) => Some(config),
-                Err(_) => None,
-            };
+            let parsed_config: Option<T> = serde_json::from_value(row.get("configuration")).ok();
 
             let item: Item<T> = Item {
                 id: row.get("id"),
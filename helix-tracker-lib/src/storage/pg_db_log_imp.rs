@@ -55,21 +55,18 @@ impl<T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync> Lo
 
         let query = "SELECT * FROM tracker.log where log.hash = $1;";
         let existing_log = client.query(query, &[&hash]).await?;
-        match existing_log.iter().next() {
+        match existing_log.first() {
             None => {
                 let query = "
                 INSERT INTO tracker.log
                 VALUES (DEFAULT,$1, $2, DEFAULT,$3)
                 RETURNING uuid, hash, created_on, data, item_;";
                 let row_inserted = client.query(query, &[&hash, &json_data, &item_id]).await?;
-                row_inserted.iter().next().unwrap();
-                match row_inserted.iter().next() {
+                row_inserted.first().unwrap();
+                match row_inserted.first() {
                     Some(row) => {
                         let parsed_payload: Option<T> =
-                            match serde_json::from_value(row.get("data")) {
-                                Ok(payload) => Some(payload),
-                                Err(_) => None,
-                            };
+                            serde_json::from_value(row.get("data")).ok();
                         Ok(Some(Log {
                             uuid: row.get("uuid"),
                             created_on: row.get("created_on"),
@@ -85,10 +82,7 @@ impl<T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync> Lo
                 }
             }
             Some(row) => {
-                let parsed_payload: Option<T> = match serde_json::from_value(row.get("data")) {
-                    Ok(payload) => Some(payload),
-                    Err(_) => None,
-                };
+                let parsed_payload: Option<T> = serde_json::from_value(row.get("data")).ok();
                 Ok(Some(Log {
                     uuid: row.get("uuid"),
                     created_on: row.get("created_on"),
@@ -122,10 +116,7 @@ impl<T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync> Lo
         let rows = client.query(query, &[&type_id, &owner_uuid]).await?;
 
         for row in rows {
-            let parsed_payload: Option<T> = match serde_json::from_value(row.get("data")) {
-                Ok(payload) => Some(payload),
-                Err(_) => None,
-            };
+            let parsed_payload: Option<T> = serde_json::from_value(row.get("data")).ok();
             result.push(Log {
                 uuid: row.get("uuid"),
                 created_on: row.get("created_on"),
@@ -159,10 +150,7 @@ impl<T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync> Lo
         let rows = client.query(query, &[&item_id, &owner_uuid]).await?;
 
         for row in rows {
-            let parsed_payload: Option<T> = match serde_json::from_value(row.get("data")) {
-                Ok(payload) => Some(payload),
-                Err(_) => None,
-            };
+            let parsed_payload: Option<T> = serde_json::from_value(row.get("data")).ok();
             result.push(Log {
                 uuid: row.get("uuid"),
                 created_on: row.get("created_on"),
@@ -203,10 +191,7 @@ impl<T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync> Lo
             .await?;
 
         for row in rows {
-            let parsed_payload: Option<T> = match serde_json::from_value(row.get("data")) {
-                Ok(payload) => Some(payload),
-                Err(_) => None,
-            };
+            let parsed_payload: Option<T> = serde_json::from_value(row.get("data")).ok();
             result.push(Log {
                 uuid: row.get("uuid"),
                 created_on: row.get("created_on"),
@@ -246,10 +231,7 @@ impl<T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync> Lo
             .await?;
 
         for row in rows {
-            let parsed_payload: Option<T> = match serde_json::from_value(row.get("data")) {
-                Ok(payload) => Some(payload),
-                Err(_) => None,
-            };
+            let parsed_payload: Option<T> = serde_json::from_value(row.get("data")).ok();
             result.push(Log {
                 uuid: row.get("uuid"),
                 created_on: row.get("created_on"),
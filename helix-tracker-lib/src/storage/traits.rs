@@ -5,6 +5,7 @@ use async_trait::async_trait;
 use serde::de::DeserializeOwned;
 
 #[async_trait]
+#[allow(clippy::ptr_arg)]
 pub trait ItemStorageTrait<T: DeserializeOwned>: Send + Sync {
     async fn get_items(
         &self,
@@ -14,6 +15,7 @@ pub trait ItemStorageTrait<T: DeserializeOwned>: Send + Sync {
 }
 
 #[async_trait]
+#[allow(clippy::ptr_arg)]
 pub trait LogStorageTrait<T: DeserializeOwned>: Send + Sync {
     async fn add_log(&self, item_id: &uuid::Uuid, payload: &T) -> StorageResult<Option<Log<T>>>;
 